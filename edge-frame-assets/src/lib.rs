@@ -0,0 +1,119 @@
+//! The web assets of `edge-frame` apps: the metadata served with each asset, the asset pack format
+//! and, with the `prepare` feature, the pipeline compressing an assets directory into an asset table
+//! or an asset pack. `edge-frame` re-exports all of it as `edge_frame::assets`.
+#![cfg_attr(not(feature = "prepare"), no_std)]
+
+/// Content types of the file extensions commonly found in web assets
+pub const MIME_TYPES: &[(&str, &str)] = &[
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("js", "text/javascript"),
+    ("mjs", "text/javascript"),
+    ("wasm", "application/wasm"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("webmanifest", "application/manifest+json"),
+    ("xml", "application/xml"),
+    ("txt", "text/plain"),
+    ("csv", "text/csv"),
+    ("md", "text/markdown"),
+    ("svg", "image/svg+xml"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("bmp", "image/bmp"),
+    ("ico", "image/x-icon"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("eot", "application/vnd.ms-fontobject"),
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("wav", "audio/wav"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+];
+
+#[derive(Debug, Clone)]
+pub struct AssetMetadata<'a> {
+    pub uri: &'a str,
+    pub cache_control: Option<&'a str>,
+    pub content_encoding: Option<&'a str>,
+    pub content_type: Option<&'a str>,
+    pub etag: Option<&'a str>,
+    /// Additional headers sent with the asset
+    pub headers: &'a [(&'a str, &'a str)],
+}
+
+impl<'a> AssetMetadata<'a> {
+    pub fn derive(uri: &str) -> AssetMetadata<'_> {
+        AssetMetadata::derive_with_mime_types(uri, &[])
+    }
+
+    /// Same as `derive`, except that the (extension, content type) pairs in `mime_types`
+    /// take precedence over the built-in `MIME_TYPES` table
+    pub fn derive_with_mime_types(
+        uri: &'a str,
+        mime_types: &[(&'a str, &'a str)],
+    ) -> AssetMetadata<'a> {
+        let file_name = uri.rsplit('/').next().unwrap_or(uri);
+
+        let mut split = file_name.split('.');
+
+        // Skip the file stem so that files without an extension have an empty suffix
+        split.next();
+
+        let suffix = split.next_back().unwrap_or("");
+
+        let (uri, content_encoding) = if suffix.eq_ignore_ascii_case("gz") {
+            (&uri[..uri.len() - 3], Some("gzip"))
+        } else if suffix.eq_ignore_ascii_case("br") {
+            (&uri[..uri.len() - 3], Some("br"))
+        } else {
+            (uri, None)
+        };
+
+        let (uri, cache_control) = if uri.eq_ignore_ascii_case("/index.html") {
+            ("/", "no-cache")
+        } else {
+            (uri, "public, max-age=31536000")
+        };
+
+        let suffix = if content_encoding.is_some() {
+            split.next_back().unwrap_or("")
+        } else {
+            suffix
+        };
+
+        let content_type = mime_types
+            .iter()
+            .chain(MIME_TYPES)
+            .find(|(extension, _)| suffix.eq_ignore_ascii_case(extension))
+            .map(|(_, content_type)| *content_type);
+
+        AssetMetadata {
+            uri,
+            cache_control: Some(cache_control),
+            content_encoding,
+            content_type,
+            etag: None,
+            headers: &[],
+        }
+    }
+}
+
+pub mod pack;
+
+#[cfg(feature = "prepare")]
+pub mod prepare;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,336 @@
+//! The asset pack format, a single binary file holding a whole asset table, which can be
+//! updated separately from the firmware (e.g. in its own flash partition).
+//!
+//! All integers are little-endian. A pack consists of:
+//! - A header of `HEADER_LEN` bytes:
+//!   - `MAGIC` (4 bytes)
+//!   - The format of the pack, `FORMAT` (`u16`)
+//!   - The number of entries (`u16`)
+//!   - The version of the pack, as given to `prepare::write_pack` (`u32`)
+//!   - The length of the index (`u32`)
+//!   - The length of the whole pack, including the header (`u32`)
+//!   - The CRC-32 (IEEE) checksum of the index and the data (`u32`)
+//! - The index, with the following fields for each entry:
+//!   - The URI, the cache control, the content encoding, the content type and the entity tag,
+//!     each as a `u16` length followed by as many bytes of UTF-8; a length of `NONE` stands for `None`,
+//!     which is not allowed for the URI
+//!   - The number of additional headers (`u8`), followed by the name and the value of each header,
+//!     in the same format as the strings above
+//!   - The offset of the data of the entry, from the start of the data (`u32`)
+//!   - The length of the data of the entry (`u32`)
+//! - The data of the entries
+//!
+//! As in an asset table, all variants of an asset are next to each other.
+
+/// The first bytes of each asset pack
+pub const MAGIC: [u8; 4] = *b"EFAP";
+
+/// The format of the asset packs written and read by this version of `edge-frame`
+pub const FORMAT: u16 = 1;
+
+pub const HEADER_LEN: usize = 24;
+
+/// The length of the strings which are `None`
+pub const NONE: u16 = u16::MAX;
+
+/// Computes the CRC-32 (IEEE) checksum of `data`
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0_u32;
+
+    for byte in data {
+        crc ^= *byte as u32;
+
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+
+    !crc
+}
+
+pub use parse::*;
+
+mod parse {
+    use core::fmt;
+
+    use super::{crc32, FORMAT, HEADER_LEN, MAGIC, NONE};
+    use crate::AssetMetadata;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum PackError {
+        /// The data does not start with `MAGIC`
+        NotAPack,
+        /// The pack is in a format other than `FORMAT`
+        UnsupportedFormat(u16),
+        /// The pack is shorter than its header says
+        Truncated,
+        /// The checksum does not match, i.e. the pack is corrupt
+        Checksum,
+        /// The index is invalid
+        Malformed,
+    }
+
+    impl fmt::Display for PackError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                Self::NotAPack => write!(f, "Not an asset pack"),
+                Self::UnsupportedFormat(format) => {
+                    write!(f, "Unsupported asset pack format {}", format)
+                }
+                Self::Truncated => write!(f, "Truncated asset pack"),
+                Self::Checksum => write!(f, "Asset pack checksum mismatch"),
+                Self::Malformed => write!(f, "Malformed asset pack index"),
+            }
+        }
+    }
+
+    /// A parsed asset pack, which borrows all of its entries from the pack data
+    #[derive(Debug, Clone, Copy)]
+    pub struct AssetPack<'a> {
+        version: u32,
+        count: u16,
+        index: &'a [u8],
+        data: &'a [u8],
+    }
+
+    impl<'a> AssetPack<'a> {
+        /// Parses the pack at the start of `pack` and checks its integrity.
+        ///
+        /// `pack` can be longer than the pack itself, e.g. a whole flash partition.
+        pub fn new(pack: &'a [u8]) -> Result<Self, PackError> {
+            if !pack.starts_with(&MAGIC) {
+                return Err(PackError::NotAPack);
+            }
+
+            if pack.len() < HEADER_LEN {
+                return Err(PackError::Truncated);
+            }
+
+            let mut header = Reader::new(&pack[MAGIC.len()..HEADER_LEN]);
+
+            let format = header.u16()?;
+
+            if format != FORMAT {
+                return Err(PackError::UnsupportedFormat(format));
+            }
+
+            let count = header.u16()?;
+            let version = header.u32()?;
+            let index_len = header.u32()? as usize;
+            let len = header.u32()? as usize;
+            let checksum = header.u32()?;
+
+            if len < HEADER_LEN || len > pack.len() {
+                return Err(PackError::Truncated);
+            }
+
+            let body = &pack[HEADER_LEN..len];
+
+            if crc32(body) != checksum {
+                return Err(PackError::Checksum);
+            }
+
+            if index_len > body.len() {
+                return Err(PackError::Malformed);
+            }
+
+            let (index, data) = body.split_at(index_len);
+
+            let pack = Self {
+                version,
+                count,
+                index,
+                data,
+            };
+
+            // Check all entries once, so that `entries` does not need to report errors
+            let mut reader = Reader::new(index);
+
+            for _ in 0..count {
+                PackEntry::read(&mut reader, data)?;
+            }
+
+            if !reader.is_empty() {
+                return Err(PackError::Malformed);
+            }
+
+            Ok(pack)
+        }
+
+        pub fn version(&self) -> u32 {
+            self.version
+        }
+
+        /// The number of entries in the pack
+        pub fn len(&self) -> usize {
+            self.count as _
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.count == 0
+        }
+
+        pub fn entries(&self) -> impl Iterator<Item = PackEntry<'a>> + Clone {
+            let data = self.data;
+            let mut reader = Reader::new(self.index);
+
+            (0..self.count).map_while(move |_| PackEntry::read(&mut reader, data).ok())
+        }
+    }
+
+    /// An entry of an asset pack
+    #[derive(Debug, Clone)]
+    pub struct PackEntry<'a> {
+        pub uri: &'a str,
+        pub cache_control: Option<&'a str>,
+        pub content_encoding: Option<&'a str>,
+        pub content_type: Option<&'a str>,
+        pub etag: Option<&'a str>,
+        pub data: &'a [u8],
+        header_count: u8,
+        headers: &'a [u8],
+    }
+
+    impl<'a> PackEntry<'a> {
+        fn read(reader: &mut Reader<'a>, data: &'a [u8]) -> Result<Self, PackError> {
+            let uri = reader.str()?.ok_or(PackError::Malformed)?;
+            let cache_control = reader.str()?;
+            let content_encoding = reader.str()?;
+            let content_type = reader.str()?;
+            let etag = reader.str()?;
+
+            let header_count = reader.u8()?;
+
+            let headers_start = reader.position();
+
+            for _ in 0..header_count {
+                reader.str()?.ok_or(PackError::Malformed)?;
+                reader.str()?.ok_or(PackError::Malformed)?;
+            }
+
+            let headers = reader.slice(headers_start);
+
+            let offset = reader.u32()? as usize;
+            let len = reader.u32()? as usize;
+
+            let data = offset
+                .checked_add(len)
+                .and_then(|end| data.get(offset..end))
+                .ok_or(PackError::Malformed)?;
+
+            Ok(Self {
+                uri,
+                cache_control,
+                content_encoding,
+                content_type,
+                etag,
+                data,
+                header_count,
+                headers,
+            })
+        }
+
+        /// The additional headers sent with the asset
+        pub fn headers(&self) -> impl Iterator<Item = (&'a str, &'a str)> + Clone {
+            let mut reader = Reader::new(self.headers);
+
+            (0..self.header_count).map_while(move |_| {
+                let name = reader.str().ok()??;
+                let value = reader.str().ok()??;
+
+                Some((name, value))
+            })
+        }
+
+        /// Returns the metadata of the entry, storing its headers in `headers`
+        pub fn metadata<'b, const N: usize>(
+            &self,
+            headers: &'b mut heapless::Vec<(&'a str, &'a str), N>,
+        ) -> AssetMetadata<'b>
+        where
+            'a: 'b,
+        {
+            headers.clear();
+
+            for header in self.headers() {
+                if headers.push(header).is_err() {
+                    break;
+                }
+            }
+
+            AssetMetadata {
+                uri: self.uri,
+                cache_control: self.cache_control,
+                content_encoding: self.content_encoding,
+                content_type: self.content_type,
+                etag: self.etag,
+                headers,
+            }
+        }
+    }
+
+    #[derive(Clone)]
+    struct Reader<'a> {
+        data: &'a [u8],
+        position: usize,
+    }
+
+    impl<'a> Reader<'a> {
+        const fn new(data: &'a [u8]) -> Self {
+            Self { data, position: 0 }
+        }
+
+        fn is_empty(&self) -> bool {
+            self.position == self.data.len()
+        }
+
+        fn position(&self) -> usize {
+            self.position
+        }
+
+        fn slice(&self, start: usize) -> &'a [u8] {
+            &self.data[start..self.position]
+        }
+
+        fn bytes(&mut self, len: usize) -> Result<&'a [u8], PackError> {
+            let bytes = self
+                .data
+                .get(self.position..self.position + len)
+                .ok_or(PackError::Malformed)?;
+
+            self.position += len;
+
+            Ok(bytes)
+        }
+
+        fn u8(&mut self) -> Result<u8, PackError> {
+            Ok(self.bytes(1)?[0])
+        }
+
+        fn u16(&mut self) -> Result<u16, PackError> {
+            let bytes = self.bytes(2)?;
+
+            Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+        }
+
+        fn u32(&mut self) -> Result<u32, PackError> {
+            let bytes = self.bytes(4)?;
+
+            Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        }
+
+        fn str(&mut self) -> Result<Option<&'a str>, PackError> {
+            let len = self.u16()?;
+
+            if len == NONE {
+                return Ok(None);
+            }
+
+            let bytes = self.bytes(len as _)?;
+
+            core::str::from_utf8(bytes)
+                .map(Some)
+                .map_err(|_| PackError::Malformed)
+        }
+    }
+}
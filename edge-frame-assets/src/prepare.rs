@@ -0,0 +1,1589 @@
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    io::Write,
+    iter,
+    ops::Range,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::{self, Context};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use brotli::CompressorWriter;
+use flate2::{Compression, GzBuilder};
+use globset::{Glob, GlobMatcher};
+use sha2::{Digest, Sha256, Sha384};
+
+use crate::{pack, AssetMetadata, MIME_TYPES};
+
+#[derive(Debug, Clone, Default)]
+pub struct Configuration {
+    /// Embed an uncompressed copy of each asset next to the gzipped one,
+    /// for HTTP clients which do not accept the `gzip` content encoding
+    pub identity: bool,
+    /// Also compress each asset with brotli, which usually results in smaller assets than gzip.
+    ///
+    /// Note that browsers only accept the `br` content encoding over HTTPS.
+    pub brotli: bool,
+    /// Keep only the smallest compressed encoding of each asset, rather than all of them,
+    /// to save flash.
+    ///
+    /// Along with `brotli`, this usually keeps only the `br` encoding, which the clients
+    /// not accepting it (e.g. all browsers over plain HTTP) then receive anyway. Only use both
+    /// when the app is served over HTTPS to browsers, or set `identity` too.
+    pub smallest: bool,
+    /// Additional (extension, content type) pairs, which take precedence over `MIME_TYPES`
+    pub mime_types: Vec<(String, String)>,
+    /// (glob, `Cache-Control` value) rules, which override the default cache policy of the assets
+    /// whose URI matches the glob, e.g. `("/sw.js", "no-cache")` or `("*.json", "public, max-age=3600")`.
+    ///
+    /// The first matching rule wins. The globs are matched against the URI of the original file,
+    /// i.e. `/index.html` rather than `/`.
+    pub cache_control: Vec<(String, String)>,
+    /// (glob, header name, header value) rules, which add headers to the assets whose URI
+    /// matches the glob, e.g. `("/index.html", "Content-Security-Policy", "default-src 'self'")`.
+    ///
+    /// All matching rules apply.
+    pub headers: Vec<(String, String, String)>,
+    /// Rename each asset (except `index.html`) to include a hash of its content, and rewrite
+    /// the references to the renamed assets in the HTML, CSS and JS assets.
+    ///
+    /// Fingerprinted assets are cached as `immutable`, as their content never changes.
+    pub fingerprint: bool,
+    /// Globs of the assets which must keep their names when fingerprinting,
+    /// e.g. `/sw.js` or `/manifest.json`
+    pub fingerprint_exclude: Vec<String>,
+    /// Fail the build if the embedded assets take more than this number of bytes
+    pub budget: Option<u64>,
+    /// Minify the HTML, CSS and JS assets before compressing them.
+    ///
+    /// Source maps (`.map` files) are not embedded when minifying, unless `source_maps` is set.
+    pub minify: bool,
+    /// Embed the source maps even when minifying
+    pub source_maps: bool,
+    /// Inline the CSS and JS assets referenced by `index.html` which take at most this number
+    /// of bytes into `index.html`, so that loading the app needs fewer requests.
+    ///
+    /// Stylesheet links become `<style>` elements, scripts become inline `<script>` elements and
+    /// the remaining quoted references (e.g. module imports) become `data:` URIs. Preload links
+    /// to the inlined assets are removed. Only the assets in the root of the assets directory
+    /// are inlined, as relative references in them are resolved against `index.html` afterwards.
+    ///
+    /// The inlined assets are not embedded separately, unless another asset references them.
+    pub inline: Option<usize>,
+    /// Also inline the WebAssembly module referenced by `index.html` as a `data:` URI,
+    /// if it takes at most `inline` bytes
+    pub inline_wasm: bool,
+    /// Add Subresource Integrity `integrity` attributes with the SHA-384 digests of the JS, CSS
+    /// and WebAssembly assets to the scripts, the stylesheet links and the preload links
+    /// referencing them in `index.html`, replacing any existing ones.
+    ///
+    /// The digests are computed after minification, fingerprinting and inlining, so they match
+    /// what the browser receives. Note that for the WebAssembly module, the browser only checks
+    /// the digest if the JS glue fetches it with the same `integrity` as the preload link.
+    pub integrity: bool,
+}
+
+/// An asset file written by `compress`
+#[derive(Debug, Clone)]
+pub struct OutputFile {
+    /// The URI of the asset, including the suffix of its content encoding (if any)
+    pub uri: String,
+    pub path: PathBuf,
+    /// The content encoding of the asset, or `None` if it is stored as-is
+    pub content_encoding: Option<&'static str>,
+    /// The size of the asset file in the assets directory
+    pub original_len: u64,
+    /// The size of the asset after minification, if it was minified
+    pub minified_len: Option<u64>,
+}
+
+pub fn run(module: impl AsRef<str>, assets_dir: impl AsRef<Path>) -> anyhow::Result<()> {
+    run_with_conf(module, assets_dir, &Default::default())
+}
+
+pub fn run_with_conf(
+    module: impl AsRef<str>,
+    assets_dir: impl AsRef<Path>,
+    conf: &Configuration,
+) -> anyhow::Result<()> {
+    let module = module.as_ref();
+    let assets_dir = assets_dir.as_ref();
+
+    let output_dir = PathBuf::new()
+        .join(env::var_os("OUT_DIR")
+            .ok_or_else(|| anyhow::anyhow!("OUT_DIR variable is not defined. You should call this code from a Cargo `build.rs` script"))?)
+        .join("edge_frame_assets")
+        .join(module);
+
+    let output_files = compress(assets_dir, &output_dir, conf, |path| {
+        println!("cargo:rerun-if-changed={}", path.display())
+    })?;
+
+    report(&output_files, conf, output_dir.join("report.json"))?;
+
+    let assets_file = output_dir.join("assets.rs");
+
+    generate(&output_files, conf, &assets_file)?;
+
+    println!(
+        "cargo:rustc-env={}_EDGE_FRAME_ASSETS={}",
+        module,
+        assets_file.display()
+    );
+
+    Ok(())
+}
+
+/// Prints the size of each asset, writes the same information as JSON to `report_file`
+/// and checks the total size of the assets against `Configuration::budget`.
+///
+/// The table is part of the build script output, which is visible with `cargo build -vv`.
+/// When the assets exceed the budget, the table is also emitted as `cargo:warning=` lines,
+/// which Cargo always shows.
+pub fn report(
+    output_files: &[OutputFile],
+    conf: &Configuration,
+    report_file: impl AsRef<Path>,
+) -> anyhow::Result<()> {
+    fn ratio(len: u64, original_len: u64) -> f64 {
+        if original_len > 0 {
+            len as f64 * 100.0 / original_len as f64
+        } else {
+            100.0
+        }
+    }
+
+    let mut entries = Vec::new();
+    let mut len = 0;
+
+    let mut lines = vec![format!(
+        "{:<60} {:>10} {:>12} {:>12} {:>12} {:>7}",
+        "Asset", "Encoding", "Original", "Minified", "Embedded", "Ratio"
+    )];
+
+    for output_file in output_files {
+        let file_len = fs::metadata(&output_file.path)
+            .with_context(|| format!("Cannot access {}", output_file.path.display()))?
+            .len();
+        let encoding = output_file.content_encoding.unwrap_or("identity");
+
+        lines.push(format!(
+            "{:<60} {:>10} {:>12} {:>12} {:>12} {:>6.1}%",
+            output_file.uri,
+            encoding,
+            output_file.original_len,
+            output_file
+                .minified_len
+                .map(|len| len.to_string())
+                .unwrap_or_else(|| "-".into()),
+            file_len,
+            ratio(file_len, output_file.original_len),
+        ));
+
+        entries.push(serde_json::json!({
+            "uri": output_file.uri,
+            "encoding": encoding,
+            "original_size": output_file.original_len,
+            "minified_size": output_file.minified_len,
+            "size": file_len,
+        }));
+
+        len += file_len;
+    }
+
+    let original_len = output_files
+        .iter()
+        .map(|output_file| output_file.original_len)
+        .sum::<u64>();
+
+    // How much smaller the minified assets got, before compression; counted once per asset
+    // rather than once per encoding
+    let minified_savings = output_files
+        .iter()
+        .filter_map(|output_file| {
+            let minified_len = output_file.minified_len?;
+            let source_uri = match output_file.content_encoding {
+                Some(_) => &output_file.uri[..output_file.uri.rfind('.').unwrap()],
+                None => &output_file.uri,
+            };
+
+            Some((
+                source_uri,
+                output_file.original_len as i64 - minified_len as i64,
+            ))
+        })
+        .collect::<BTreeMap<_, _>>()
+        .values()
+        .sum::<i64>();
+
+    lines.push(format!(
+        "{:<60} {:>10} {:>12} {:>12} {:>12} {:>6.1}%",
+        "Total",
+        "",
+        original_len,
+        "",
+        len,
+        ratio(len, original_len),
+    ));
+
+    if conf.minify {
+        lines.push(format!("Minification saved {} bytes", minified_savings));
+    }
+
+    let over_budget = conf.budget.filter(|budget| len > *budget);
+
+    for line in &lines {
+        println!("{}", line);
+
+        if over_budget.is_some() {
+            println!("cargo:warning={}", line);
+        }
+    }
+
+    let report = serde_json::json!({
+        "assets": entries,
+        "original_size": original_len,
+        "minified_savings": minified_savings,
+        "size": len,
+        "budget": conf.budget,
+    });
+
+    let report_file = report_file.as_ref();
+
+    fs::write(report_file, serde_json::to_string_pretty(&report)?)
+        .with_context(|| format!("Cannot write {}", report_file.display()))?;
+
+    if let Some(budget) = over_budget {
+        anyhow::bail!(
+            "The embedded assets take {} bytes, which exceeds the budget of {} bytes by {} bytes",
+            len,
+            budget,
+            len - budget
+        );
+    }
+
+    Ok(())
+}
+
+pub fn generate(
+    output_files: &[OutputFile],
+    conf: &Configuration,
+    assets_file: impl AsRef<Path>,
+) -> anyhow::Result<()> {
+    let assets = table(output_files, conf, |output_file, _| {
+        format!("include_bytes!({:?})", output_file.display().to_string())
+    })?;
+
+    let assets_file = assets_file.as_ref();
+
+    fs::write(assets_file, assets)
+        .with_context(|| format!("Cannot write {}", assets_file.display()))?;
+
+    Ok(())
+}
+
+/// Returns the Rust source of the asset table, i.e. an expression of type `assets::serve::Assets`
+/// which expects `AssetMetadata` to be in scope.
+///
+/// `content` returns the expression for the content of each asset file, given its path and data.
+pub fn table(
+    output_files: &[OutputFile],
+    conf: &Configuration,
+    content: impl Fn(&Path, &[u8]) -> String,
+) -> anyhow::Result<String> {
+    let mut assets = String::new();
+
+    assets.push_str("&[\n");
+
+    for_each_asset(output_files, conf, |metadata, output_file, data| {
+        assets.push_str(&format!(
+            "    ({}, {}),\n",
+            source(metadata),
+            content(output_file, data),
+        ));
+
+        Ok(())
+    })?;
+
+    assets.push_str("]\n");
+
+    Ok(assets)
+}
+
+/// Compresses the assets in `assets_dir` and writes them into the asset pack `pack_file`
+/// (see `assets::pack`), which can be served with `serve::AssetPackHandler`.
+pub fn run_pack(
+    assets_dir: impl AsRef<Path>,
+    pack_file: impl AsRef<Path>,
+    version: u32,
+    conf: &Configuration,
+) -> anyhow::Result<()> {
+    // The compressed files are only needed until they are written into the pack
+    with_temp_dir("edge-frame-pack", |output_dir| {
+        let output_files = compress(assets_dir, output_dir, conf, |_| ())?;
+
+        write_pack(&output_files, conf, version, pack_file)
+    })
+}
+
+/// Calls `f` with a new directory in the temporary directory of the system, named after `name`,
+/// and removes the directory afterwards.
+///
+/// The directory is unique to each call, so that concurrent calls (e.g. from parallel tests
+/// or proc macro expansions) do not overwrite each other's files.
+pub fn with_temp_dir<T>(
+    name: &str,
+    f: impl FnOnce(&Path) -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    let dir = env::temp_dir().join(format!(
+        "{}-{}-{}",
+        name,
+        process::id(),
+        CALLS.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = f(&dir);
+
+    let _ = fs::remove_dir_all(&dir);
+
+    result
+}
+
+/// Writes the asset files into the asset pack `pack_file` (see `assets::pack`)
+pub fn write_pack(
+    output_files: &[OutputFile],
+    conf: &Configuration,
+    version: u32,
+    pack_file: impl AsRef<Path>,
+) -> anyhow::Result<()> {
+    fn write_str(index: &mut Vec<u8>, value: Option<&str>) -> anyhow::Result<()> {
+        match value {
+            Some(value) => {
+                let len = u16::try_from(value.len())
+                    .ok()
+                    .filter(|len| *len != pack::NONE)
+                    .ok_or_else(|| anyhow::anyhow!("{} is too long for an asset pack", value))?;
+
+                index.extend_from_slice(&len.to_le_bytes());
+                index.extend_from_slice(value.as_bytes());
+            }
+            None => index.extend_from_slice(&pack::NONE.to_le_bytes()),
+        }
+
+        Ok(())
+    }
+
+    fn to_u32(value: usize) -> anyhow::Result<u32> {
+        u32::try_from(value).map_err(|_| anyhow::anyhow!("The asset pack is too large"))
+    }
+
+    let mut index = Vec::new();
+    let mut data = Vec::new();
+
+    for_each_asset(output_files, conf, |metadata, _, content| {
+        write_str(&mut index, Some(metadata.uri))?;
+        write_str(&mut index, metadata.cache_control)?;
+        write_str(&mut index, metadata.content_encoding)?;
+        write_str(&mut index, metadata.content_type)?;
+        write_str(&mut index, metadata.etag)?;
+
+        index.push(u8::try_from(metadata.headers.len()).map_err(|_| {
+            anyhow::anyhow!("{} has too many headers for an asset pack", metadata.uri)
+        })?);
+
+        for (name, value) in metadata.headers {
+            write_str(&mut index, Some(name))?;
+            write_str(&mut index, Some(value))?;
+        }
+
+        index.extend_from_slice(&to_u32(data.len())?.to_le_bytes());
+        index.extend_from_slice(&to_u32(content.len())?.to_le_bytes());
+
+        data.extend_from_slice(content);
+
+        Ok(())
+    })?;
+
+    let count = u16::try_from(output_files.len())
+        .map_err(|_| anyhow::anyhow!("Too many assets for an asset pack"))?;
+
+    let body = [index.as_slice(), data.as_slice()].concat();
+
+    let mut packed = Vec::with_capacity(pack::HEADER_LEN + body.len());
+
+    packed.extend_from_slice(&pack::MAGIC);
+    packed.extend_from_slice(&pack::FORMAT.to_le_bytes());
+    packed.extend_from_slice(&count.to_le_bytes());
+    packed.extend_from_slice(&version.to_le_bytes());
+    packed.extend_from_slice(&to_u32(index.len())?.to_le_bytes());
+    packed.extend_from_slice(&to_u32(pack::HEADER_LEN + body.len())?.to_le_bytes());
+    packed.extend_from_slice(&pack::crc32(&body).to_le_bytes());
+    packed.extend_from_slice(&body);
+
+    let pack_file = pack_file.as_ref();
+
+    fs::write(pack_file, packed)
+        .with_context(|| format!("Cannot write {}", pack_file.display()))?;
+
+    Ok(())
+}
+
+/// Calls `f` with the metadata, the path and the data of each asset file
+fn for_each_asset(
+    output_files: &[OutputFile],
+    conf: &Configuration,
+    mut f: impl FnMut(&AssetMetadata<'_>, &Path, &[u8]) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mime_types = conf
+        .mime_types
+        .iter()
+        .map(|(extension, content_type)| (extension.as_str(), content_type.as_str()))
+        .collect::<Vec<_>>();
+
+    let cache_control = conf
+        .cache_control
+        .iter()
+        .map(|(glob, cache_control)| Ok((matcher(glob)?, cache_control.as_str())))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let headers = conf
+        .headers
+        .iter()
+        .map(|(glob, name, value)| Ok((matcher(glob)?, (name.as_str(), value.as_str()))))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let is_fingerprinted = is_fingerprinted(conf)?;
+
+    for OutputFile {
+        uri,
+        path: output_file,
+        content_encoding,
+        ..
+    } in output_files
+    {
+        let data = fs::read(output_file)
+            .with_context(|| format!("Cannot read {}", output_file.display()))?;
+
+        let etag = etag(&data);
+
+        let mut metadata = AssetMetadata {
+            etag: Some(&etag),
+            ..AssetMetadata::derive_with_mime_types(uri, &mime_types)
+        };
+
+        if content_encoding.is_none() && metadata.content_encoding.is_some() {
+            // A `.gz` or `.br` file stored as-is, rather than a compressed variant of another asset
+            let extension = extension(uri);
+
+            metadata.uri = uri;
+            metadata.content_encoding = None;
+            metadata.content_type = mime_types
+                .iter()
+                .chain(MIME_TYPES)
+                .find(|(candidate, _)| candidate.eq_ignore_ascii_case(&extension))
+                .map(|(_, content_type)| *content_type);
+        }
+
+        // The URI of the original file, without the suffix of the content encoding
+        let source_uri = match metadata.content_encoding {
+            Some(_) => &uri[..uri.rfind('.').unwrap()],
+            None => uri,
+        };
+
+        if is_fingerprinted(source_uri) {
+            metadata.cache_control = Some("public, max-age=31536000, immutable");
+        }
+
+        if let Some((_, value)) = cache_control
+            .iter()
+            .find(|(matcher, _)| matcher.is_match(source_uri))
+        {
+            metadata.cache_control = Some(value);
+        }
+
+        let asset_headers = headers
+            .iter()
+            .filter(|(matcher, _)| matcher.is_match(source_uri))
+            .map(|(_, header)| *header)
+            .collect::<Vec<_>>();
+
+        metadata.headers = &asset_headers;
+
+        f(&metadata, output_file, &data)?;
+    }
+
+    Ok(())
+}
+
+fn matcher(glob: &str) -> anyhow::Result<GlobMatcher> {
+    Ok(Glob::new(glob)
+        .map_err(|err| anyhow::anyhow!("Invalid glob {glob}: {err}"))?
+        .compile_matcher())
+}
+
+/// A strong entity tag derived from the SHA-256 digest of the (compressed) asset data
+pub fn etag(data: &[u8]) -> String {
+    let digest = Sha256::digest(data);
+
+    let hash = digest[..16]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+
+    format!("\"{}\"", hash)
+}
+
+fn source(metadata: &AssetMetadata<'_>) -> String {
+    fn option(value: Option<&str>) -> String {
+        value
+            .map(|value| format!("Some({:?})", value))
+            .unwrap_or_else(|| "None".into())
+    }
+
+    let headers = metadata
+        .headers
+        .iter()
+        .map(|(name, value)| format!("({:?}, {:?})", name, value))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    format!(
+        "AssetMetadata {{ uri: {:?}, cache_control: {}, content_encoding: {}, content_type: {}, etag: {}, headers: &[{}] }}",
+        metadata.uri,
+        option(metadata.cache_control),
+        option(metadata.content_encoding),
+        option(metadata.content_type),
+        option(metadata.etag),
+        headers,
+    )
+}
+
+pub fn compress(
+    assets_dir: impl AsRef<Path>,
+    output_dir: impl AsRef<Path>,
+    conf: &Configuration,
+    track: impl Fn(&Path),
+) -> anyhow::Result<Vec<OutputFile>> {
+    let mut source_files = Vec::new();
+
+    collect(assets_dir.as_ref(), "", &track, &mut source_files)?;
+
+    if conf.minify && !conf.source_maps {
+        source_files.retain(|(uri, _)| !uri.ends_with(".map"));
+    }
+
+    // `read_dir` returns the files in an unspecified order; sort them by URI so that
+    // the same assets always produce the same asset table and fingerprints
+    source_files.sort();
+
+    let mut assets = source_files
+        .into_iter()
+        .map(|(uri, source_file)| {
+            let data = fs::read(&source_file)
+                .with_context(|| format!("Cannot read {}", source_file.display()))?;
+
+            Ok((uri, data))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    // The (original, minified) sizes of the assets
+    let mut lens = assets
+        .iter()
+        .map(|(_, data)| (data.len() as u64, None))
+        .collect::<Vec<_>>();
+
+    if conf.minify {
+        for ((uri, data), (_, minified_len)) in assets.iter_mut().zip(&mut lens) {
+            if minify(uri, data)? {
+                *minified_len = Some(data.len() as u64);
+            }
+        }
+    }
+
+    if conf.fingerprint {
+        fingerprint(&mut assets, conf)?;
+    }
+
+    let inlined = match conf.inline {
+        Some(threshold) => inline(&mut assets, threshold, conf)?,
+        None => Vec::new(),
+    };
+
+    if conf.integrity {
+        integrity(&mut assets)?;
+    }
+
+    let mut output_files = Vec::new();
+
+    for ((uri, data), lens) in assets.iter().zip(lens) {
+        if inlined.contains(uri) {
+            continue;
+        }
+
+        encode(
+            uri,
+            data,
+            lens,
+            output_dir.as_ref(),
+            conf,
+            &track,
+            &mut output_files,
+        )?;
+    }
+
+    Ok(output_files)
+}
+
+fn collect(
+    dir: &Path,
+    uri_prefix: &str,
+    track: &impl Fn(&Path),
+    source_files: &mut Vec<(String, PathBuf)>,
+) -> anyhow::Result<()> {
+    track(dir);
+
+    let files = fs::read_dir(dir).with_context(|| format!("Cannot read {}", dir.display()))?;
+
+    for file in files {
+        let file = file.with_context(|| format!("Cannot read {}", dir.display()))?;
+        let path = file.path();
+
+        let metadata = file
+            .metadata()
+            .with_context(|| format!("Cannot access {}", path.display()))?;
+
+        let file_name = file.file_name();
+        let file_name = file_name
+            .to_str()
+            .filter(|file_name| file_name.chars().all(is_uri_char))
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "The name of {} cannot be used in an asset URI: it must consist of printable ASCII characters other than \" # % < > ? \\ ^ ` {{ | }}",
+                    path.display()
+                )
+            })?;
+
+        let uri = format!("{}/{}", uri_prefix, file_name);
+
+        if metadata.is_dir() {
+            collect(&path, &uri, track, source_files)?;
+        } else if metadata.is_file() {
+            track(&path);
+
+            source_files.push((uri, path));
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether `c` can appear in an URI path as-is, i.e. without being percent-encoded
+fn is_uri_char(c: char) -> bool {
+    c.is_ascii_graphic() && !"\"#%<>?\\^`{|}".contains(c)
+}
+
+fn encode(
+    uri: &str,
+    data: &[u8],
+    (original_len, minified_len): (u64, Option<u64>),
+    output_dir: &Path,
+    conf: &Configuration,
+    track: &impl Fn(&Path),
+    output_files: &mut Vec<OutputFile>,
+) -> anyhow::Result<()> {
+    if is_incompressible(uri) {
+        store(
+            uri,
+            data,
+            output_dir,
+            (original_len, minified_len),
+            track,
+            output_files,
+        )?;
+
+        return Ok(());
+    }
+
+    let mut variants = Vec::new();
+
+    let gz_file = output_file(output_dir, &format!("{}.gz", uri), Some("gzip"))?;
+
+    // A fixed modification time and no file name in the header keep the output reproducible
+    fs::File::create(&gz_file)
+        .and_then(|file| {
+            let mut encoder = GzBuilder::new().mtime(0).write(file, Compression::best());
+            encoder.write_all(data)?;
+            encoder.finish()
+        })
+        .with_context(|| format!("Cannot write {}", gz_file.display()))?;
+
+    variants.push((format!("{}.gz", uri), gz_file, "gzip"));
+
+    if conf.brotli {
+        let br_file = output_file(output_dir, &format!("{}.br", uri), Some("br"))?;
+
+        fs::File::create(&br_file)
+            .and_then(|file| {
+                let mut encoder = CompressorWriter::new(file, 4096, 11, 22);
+                encoder.write_all(data)?;
+                encoder.into_inner().flush()
+            })
+            .with_context(|| format!("Cannot write {}", br_file.display()))?;
+
+        variants.push((format!("{}.br", uri), br_file, "br"));
+    }
+
+    let mut variants = variants
+        .into_iter()
+        .map(|(uri, path, content_encoding)| {
+            let len = fs::metadata(&path)
+                .with_context(|| format!("Cannot access {}", path.display()))?
+                .len();
+
+            Ok((uri, path, content_encoding, len))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    variants.sort_by_key(|(_, _, _, len)| *len);
+
+    // Drop the encodings which do not make the asset smaller, and with `Configuration::smallest`,
+    // all encodings but the smallest one
+    let (variants, dropped): (Vec<_>, Vec<_>) =
+        variants
+            .into_iter()
+            .enumerate()
+            .partition(|(index, (_, _, _, len))| {
+                *len < data.len() as u64 && (!conf.smallest || *index == 0)
+            });
+
+    for (_, (_, output_file, _, _)) in dropped {
+        fs::remove_file(&output_file)
+            .with_context(|| format!("Cannot remove {}", output_file.display()))?;
+    }
+
+    // Without a gzip variant, the asset is stored as-is too, for the clients which do not accept
+    // the other encodings; unless only the smallest encoding is to be kept
+    let fallback = if conf.smallest {
+        !variants.is_empty()
+    } else {
+        variants
+            .iter()
+            .any(|(_, (_, _, content_encoding, _))| *content_encoding == "gzip")
+    };
+
+    for (_, (uri, path, content_encoding, _)) in variants {
+        track(&path);
+
+        output_files.push(OutputFile {
+            uri,
+            path,
+            content_encoding: Some(content_encoding),
+            original_len,
+            minified_len,
+        });
+    }
+
+    if conf.identity || !fallback {
+        store(
+            uri,
+            data,
+            output_dir,
+            (original_len, minified_len),
+            track,
+            output_files,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Stores the asset as-is, without any content encoding
+fn store(
+    uri: &str,
+    data: &[u8],
+    output_dir: &Path,
+    (original_len, minified_len): (u64, Option<u64>),
+    track: &impl Fn(&Path),
+    output_files: &mut Vec<OutputFile>,
+) -> anyhow::Result<()> {
+    let output_file = output_file(output_dir, uri, None)?;
+
+    track(&output_file);
+
+    fs::write(&output_file, data)
+        .with_context(|| format!("Cannot write {}", output_file.display()))?;
+
+    output_files.push(OutputFile {
+        uri: uri.to_string(),
+        path: output_file,
+        content_encoding: None,
+        original_len,
+        minified_len,
+    });
+
+    Ok(())
+}
+
+/// Whether `uri` is in a format which is compressed already, so that compressing it again
+/// would not make it (noticeably) smaller
+fn is_incompressible(uri: &str) -> bool {
+    const INCOMPRESSIBLE: &[&str] = &[
+        "png", "jpg", "jpeg", "gif", "webp", "avif", "woff", "woff2", "mp3", "ogg", "mp4", "webm",
+        "zip", "gz", "br",
+    ];
+
+    INCOMPRESSIBLE.contains(&extension(uri).as_str())
+}
+
+/// Replaces `data` with its minified version, if `uri` is a HTML, CSS or JS asset,
+/// and returns whether it did
+fn minify(uri: &str, data: &mut Vec<u8>) -> anyhow::Result<bool> {
+    let Some(minifier) = find_minifier(uri) else {
+        return Ok(false);
+    };
+
+    let text = std::str::from_utf8(data)
+        .with_context(|| format!("Cannot minify {}: not valid UTF-8", uri))?;
+
+    let minified =
+        minifier(text).map_err(|err| anyhow::anyhow!("Cannot minify {}: {}", uri, err))?;
+
+    *data = minified.into_bytes();
+
+    Ok(true)
+}
+
+type Minifier = fn(&str) -> Result<String, &'static str>;
+
+fn find_minifier(uri: &str) -> Option<Minifier> {
+    match extension(uri).as_str() {
+        "html" | "htm" => Some(|text| Ok(minify_html(text))),
+        "css" => Some(|text| minifier::css::minify(text).map(|minified| minified.to_string())),
+        "js" | "mjs" => {
+            Some(|text| minifier::js::minify(text).map(|minified| minified.to_string()))
+        }
+        _ => None,
+    }
+}
+
+/// A conservative HTML minifier, which only removes comments and collapses the whitespace
+/// between and around the tags.
+///
+/// The tags themselves, as well as the content of the `pre`, `textarea`, `script` and `style`
+/// elements, are kept as-is.
+fn minify_html(html: &str) -> String {
+    let mut minified = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(c) = rest.chars().next() {
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            // Keep conditional comments, which are not really comments
+            if !comment.starts_with("[if") {
+                rest = comment
+                    .find("-->")
+                    .map(|end| &comment[end + 3..])
+                    .unwrap_or("");
+                continue;
+            }
+        }
+
+        if let Some((tag, name)) = tag(rest) {
+            minified.push_str(tag);
+            rest = &rest[tag.len()..];
+
+            let content_len = raw_content_len(rest, name);
+
+            minified.push_str(&rest[..content_len]);
+            rest = &rest[content_len..];
+        } else if c.is_ascii_whitespace() {
+            let end = rest
+                .find(|c: char| !c.is_ascii_whitespace())
+                .unwrap_or(rest.len());
+
+            minified.push(if rest[..end].contains('\n') {
+                '\n'
+            } else {
+                ' '
+            });
+            rest = &rest[end..];
+        } else {
+            minified.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+
+    minified
+}
+
+/// Splits the tag at the start of `html` (if any) and returns it along with its element name,
+/// which is empty for closing tags, comments and doctypes
+fn tag(html: &str) -> Option<(&str, &str)> {
+    if !html.starts_with('<')
+        || !html[1..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '/' || c == '!')
+    {
+        return None;
+    }
+
+    // The tag ends with the first `>` which is not part of a quoted attribute value
+    let mut quote = None;
+    let end = html
+        .char_indices()
+        .find(|(_, c)| {
+            match quote {
+                Some(q) if q == *c => quote = None,
+                Some(_) => (),
+                None if *c == '"' || *c == '\'' => quote = Some(*c),
+                None => return *c == '>',
+            }
+
+            false
+        })
+        .map(|(index, _)| index + 1)
+        .unwrap_or(html.len());
+
+    let tag = &html[..end];
+
+    let name = tag[1..]
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .next()
+        .unwrap_or("");
+
+    Some((tag, name))
+}
+
+/// The length of the content at the start of `html` of the element `name`, if it is one of
+/// the elements whose content must be kept as-is (`pre`, `textarea`, `script` and `style`)
+fn raw_content_len(html: &str, name: &str) -> usize {
+    const RAW_ELEMENTS: &[&str] = &["pre", "textarea", "script", "style"];
+
+    match RAW_ELEMENTS
+        .iter()
+        .find(|element| element.eq_ignore_ascii_case(name))
+    {
+        Some(element) => html
+            .to_ascii_lowercase()
+            .find(&format!("</{}", element))
+            .unwrap_or(html.len()),
+        None => 0,
+    }
+}
+
+/// Returns the (name, value) pairs of the attributes of `tag`; attributes without a value
+/// have an empty one
+fn attributes(tag: &str) -> Vec<(&str, &str)> {
+    let mut attributes = Vec::new();
+
+    let tag = tag.strip_suffix('>').unwrap_or(tag);
+    let mut rest = tag[1..].trim_start_matches(|c: char| c.is_ascii_alphanumeric());
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == '/');
+
+        if rest.is_empty() {
+            break;
+        }
+
+        let name_end = rest
+            .find(|c: char| c.is_ascii_whitespace() || c == '=' || c == '/')
+            .unwrap_or(rest.len());
+
+        let name = &rest[..name_end];
+        rest = rest[name_end..].trim_start();
+
+        let mut value = "";
+
+        if let Some(after) = rest.strip_prefix('=') {
+            let after = after.trim_start();
+
+            (value, rest) = match after.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let after = &after[1..];
+                    let end = after.find(quote).unwrap_or(after.len());
+
+                    (&after[..end], after.get(end + 1..).unwrap_or(""))
+                }
+                _ => after.split_at(
+                    after
+                        .find(|c: char| c.is_ascii_whitespace())
+                        .unwrap_or(after.len()),
+                ),
+            };
+        }
+
+        attributes.push((name, value));
+    }
+
+    attributes
+}
+
+/// Copies `html`, replacing each tag for which `rewrite` returns a replacement.
+///
+/// `rewrite` gets the tag, its element name and the rest of `html` after the tag, and returns
+/// the replacement along with the number of bytes of the rest which it replaces as well.
+fn rewrite_tags(
+    html: &str,
+    mut rewrite: impl FnMut(&str, &str, &str) -> Option<(String, usize)>,
+) -> String {
+    let mut rewritten = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(c) = rest.chars().next() {
+        let Some((tag, name)) = tag(rest) else {
+            rewritten.push(c);
+            rest = &rest[c.len_utf8()..];
+            continue;
+        };
+
+        rest = &rest[tag.len()..];
+
+        if let Some((replacement, consumed)) = rewrite(tag, name, rest) {
+            rewritten.push_str(&replacement);
+            rest = &rest[consumed..];
+        } else {
+            let content_len = raw_content_len(rest, name);
+
+            rewritten.push_str(tag);
+            rewritten.push_str(&rest[..content_len]);
+            rest = &rest[content_len..];
+        }
+    }
+
+    rewritten
+}
+
+fn attribute<'a>(attributes: &[(&str, &'a str)], name: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|(attribute, _)| attribute.eq_ignore_ascii_case(name))
+        .map(|(_, value)| *value)
+}
+
+/// Formats (name, value) attribute pairs as returned by `attributes` for inclusion in a tag
+fn format_attributes<'a>(attributes: impl Iterator<Item = &'a (&'a str, &'a str)>) -> String {
+    attributes
+        .map(|(name, value)| {
+            if value.is_empty() {
+                format!(" {}", name)
+            } else if value.contains('"') {
+                format!(" {}='{}'", name, value)
+            } else {
+                format!(" {}=\"{}\"", name, value)
+            }
+        })
+        .collect()
+}
+
+/// Returns the path of the output file with `uri` and `content_encoding`, creating its directory.
+///
+/// The files of each content encoding go to their own directory, so that e.g. the gzip variant
+/// of `/app.js` and a precompressed `/app.js.gz` stored as-is do not overwrite each other.
+fn output_file(
+    output_dir: &Path,
+    uri: &str,
+    content_encoding: Option<&str>,
+) -> anyhow::Result<PathBuf> {
+    let output_file = output_dir
+        .join(content_encoding.unwrap_or("identity"))
+        .join(&uri[1..]);
+
+    if let Some(parent) = output_file.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Cannot create {}", parent.display()))?;
+    }
+
+    Ok(output_file)
+}
+
+/// Renames the assets to include a hash of their content, and rewrites the references to
+/// the renamed assets in the HTML, CSS and JS assets.
+///
+/// As CSS and JS assets might reference any other asset, their hash covers the content of
+/// all other CSS and JS assets and the fingerprints of all remaining assets as well.
+fn fingerprint(assets: &mut [(String, Vec<u8>)], conf: &Configuration) -> anyhow::Result<()> {
+    let is_fingerprinted = is_fingerprinted(conf)?;
+
+    let mut renames = assets
+        .iter()
+        .filter(|(uri, _)| is_fingerprinted(uri) && !is_text(uri))
+        .map(|(uri, data)| (uri.clone(), fingerprinted(uri, data)))
+        .collect::<Vec<_>>();
+
+    let mut text_hasher = Sha256::new();
+
+    for (uri, new_uri) in &renames {
+        text_hasher.update(uri);
+        text_hasher.update(new_uri);
+    }
+
+    for (uri, data) in assets.iter().filter(|(uri, _)| is_text(uri)) {
+        text_hasher.update(uri);
+        text_hasher.update(data);
+    }
+
+    let text_hash = text_hasher.finalize();
+
+    renames.extend(
+        assets
+            .iter()
+            .filter(|(uri, _)| is_fingerprinted(uri) && is_text(uri))
+            .map(|(uri, data)| {
+                (
+                    uri.clone(),
+                    fingerprinted(uri, &[data, &text_hash[..]].concat()),
+                )
+            }),
+    );
+
+    let renames = renames.into_iter().collect::<BTreeMap<_, _>>();
+
+    for (uri, data) in assets.iter_mut() {
+        if is_text(uri) {
+            *data = replace_references(uri, data, &renames);
+        }
+
+        if let Some(new_uri) = renames.get(uri) {
+            *uri = new_uri.clone();
+        }
+    }
+
+    Ok(())
+}
+
+/// Inlines the small CSS and JS assets (and optionally the WebAssembly module) into `index.html`,
+/// as described by `Configuration::inline`, and returns the URIs of the inlined assets
+/// which need not be embedded anymore
+fn inline(
+    assets: &mut [(String, Vec<u8>)],
+    threshold: usize,
+    conf: &Configuration,
+) -> anyhow::Result<Vec<String>> {
+    let Some(index) = assets
+        .iter()
+        .position(|(uri, _)| uri.eq_ignore_ascii_case("/index.html"))
+    else {
+        return Ok(Vec::new());
+    };
+
+    let mime_types = conf
+        .mime_types
+        .iter()
+        .map(|(extension, content_type)| (extension.as_str(), content_type.as_str()))
+        .collect::<Vec<_>>();
+
+    let is_css = |uri: &str| extension(uri) == "css";
+    let is_js = |uri: &str| ["js", "mjs"].contains(&extension(uri).as_str());
+
+    let candidates = assets
+        .iter()
+        .enumerate()
+        .filter(|(candidate, (uri, data))| {
+            *candidate != index
+                && data.len() <= threshold
+                && !uri[1..].contains('/')
+                && (is_css(uri) || is_js(uri) || conf.inline_wasm && extension(uri) == "wasm")
+        })
+        .map(|(_, (uri, data))| (uri.as_str(), data.as_slice()))
+        .collect::<Vec<_>>();
+
+    let candidate = |reference: &str| {
+        candidates
+            .iter()
+            .copied()
+            .find(|(uri, _)| references(uri).iter().any(|form| form == reference))
+    };
+
+    let html = std::str::from_utf8(&assets[index].1)
+        .context("Cannot inline assets into /index.html: not valid UTF-8")?;
+
+    let mut inlined = Vec::new();
+
+    // First, replace the stylesheet links and the scripts with the assets themselves
+    let mut html = rewrite_tags(html, |tag, name, rest| {
+        let attributes = attributes(tag);
+
+        if name.eq_ignore_ascii_case("link") {
+            let (uri, data) = attribute(&attributes, "href").and_then(candidate)?;
+
+            let stylesheet = attribute(&attributes, "rel")?
+                .split_ascii_whitespace()
+                .any(|rel| rel.eq_ignore_ascii_case("stylesheet"));
+
+            if !stylesheet || !is_css(uri) {
+                return None;
+            }
+
+            let css = std::str::from_utf8(data).ok()?;
+
+            let media = attribute(&attributes, "media")
+                .map(|media| format!(" media=\"{}\"", media))
+                .unwrap_or_default();
+
+            inlined.push(uri.to_string());
+
+            Some((
+                format!(
+                    "<style{}>{}</style>",
+                    media,
+                    css.replace("</style", "<\\/style")
+                ),
+                0,
+            ))
+        } else if name.eq_ignore_ascii_case("script") {
+            let (uri, data) = attribute(&attributes, "src").and_then(candidate)?;
+
+            if !is_js(uri) {
+                return None;
+            }
+
+            let js = std::str::from_utf8(data).ok()?;
+
+            let attributes = format_attributes(
+                attributes
+                    .iter()
+                    .filter(|(name, _)| !name.eq_ignore_ascii_case("src")),
+            );
+
+            // Also replace the (empty) content and the closing tag of the original script
+            let content_len = raw_content_len(rest, name);
+            let consumed = rest[content_len..]
+                .find('>')
+                .map(|end| content_len + end + 1)
+                .unwrap_or(rest.len());
+
+            inlined.push(uri.to_string());
+
+            Some((
+                format!(
+                    "<script{}>{}</script>",
+                    attributes,
+                    js.replace("</script", "<\\/script")
+                ),
+                consumed,
+            ))
+        } else {
+            None
+        }
+    });
+
+    // Then, replace the remaining quoted references with `data:` URIs
+    for (uri, data) in &candidates {
+        // Relative imports of other JS assets would not resolve from a `data:` URI
+        if is_js(uri)
+            && assets.iter().any(|(other, _)| {
+                other != uri && is_js(other) && contains_reference(uri, data, other)
+            })
+        {
+            continue;
+        }
+
+        let content_type = AssetMetadata::derive_with_mime_types(uri, &mime_types)
+            .content_type
+            .unwrap_or("application/octet-stream");
+
+        let data_uri = format!("data:{};base64,{}", content_type, BASE64.encode(data));
+
+        for quote in ['"', '\'', '`'] {
+            for reference in references(uri) {
+                let reference = format!("{}{}{}", quote, reference, quote);
+
+                if html.contains(&reference) {
+                    html = html.replace(&reference, &format!("{}{}{}", quote, data_uri, quote));
+
+                    inlined.push(uri.to_string());
+                }
+            }
+        }
+    }
+
+    inlined.sort();
+    inlined.dedup();
+
+    // The inlined assets might still be needed by `index.html` or by the assets which were not inlined
+    let referrers = assets
+        .iter()
+        .enumerate()
+        .filter(|(other, (other_uri, _))| {
+            *other != index && is_text(other_uri) && !inlined.contains(other_uri)
+        })
+        .map(|(_, (other_uri, data))| (other_uri.as_str(), data.as_slice()))
+        .collect::<Vec<_>>();
+
+    inlined.retain(|uri| {
+        !contains_reference("/index.html", html.as_bytes(), uri)
+            && !referrers.iter().any(|(other_uri, data)| {
+                other_uri != uri && contains_reference(other_uri, data, uri)
+            })
+    });
+
+    // Finally, remove the preload links to the inlined assets
+    let html = rewrite_tags(&html, |tag, name, _| {
+        let attributes = attributes(tag);
+
+        let preload = attribute(&attributes, "rel")?
+            .split_ascii_whitespace()
+            .any(|rel| {
+                rel.eq_ignore_ascii_case("preload") || rel.eq_ignore_ascii_case("modulepreload")
+            });
+
+        let href = attribute(&attributes, "href")?;
+
+        let inlined = href.starts_with("data:")
+            || inlined
+                .iter()
+                .any(|uri| references(uri).iter().any(|form| form == href));
+
+        (name.eq_ignore_ascii_case("link") && preload && inlined).then(|| (String::new(), 0))
+    });
+
+    assets[index].1 = html.into_bytes();
+
+    Ok(inlined)
+}
+
+/// Adds `integrity` attributes with the SHA-384 digests of the JS, CSS and WebAssembly assets
+/// to the scripts, the stylesheet links and the preload links referencing them in `index.html`,
+/// as described by `Configuration::integrity`
+fn integrity(assets: &mut [(String, Vec<u8>)]) -> anyhow::Result<()> {
+    let Some(index) = assets
+        .iter()
+        .position(|(uri, _)| uri.eq_ignore_ascii_case("/index.html"))
+    else {
+        return Ok(());
+    };
+
+    let digests = assets
+        .iter()
+        .filter(|(uri, _)| ["js", "mjs", "css", "wasm"].contains(&extension(uri).as_str()))
+        .map(|(uri, data)| {
+            (
+                uri.as_str(),
+                format!("sha384-{}", BASE64.encode(Sha384::digest(data))),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let digest = |reference: &str| {
+        digests
+            .iter()
+            .find(|(uri, _)| references(uri).iter().any(|form| form == reference))
+            .map(|(_, digest)| digest.as_str())
+    };
+
+    let html = std::str::from_utf8(&assets[index].1)
+        .context("Cannot add integrity attributes to /index.html: not valid UTF-8")?;
+
+    let html = rewrite_tags(html, |tag, name, rest| {
+        let attributes = attributes(tag);
+
+        let digest = if name.eq_ignore_ascii_case("link") {
+            let subresource = attribute(&attributes, "rel")?
+                .split_ascii_whitespace()
+                .any(|rel| {
+                    ["stylesheet", "preload", "modulepreload"]
+                        .iter()
+                        .any(|candidate| rel.eq_ignore_ascii_case(candidate))
+                });
+
+            if !subresource {
+                return None;
+            }
+
+            attribute(&attributes, "href").and_then(digest)?
+        } else if name.eq_ignore_ascii_case("script") {
+            attribute(&attributes, "src").and_then(digest)?
+        } else {
+            return None;
+        };
+
+        // Any existing digest is stale, as minifying or fingerprinting might have changed the asset
+        let attributes = format_attributes(
+            attributes
+                .iter()
+                .filter(|(name, _)| !name.eq_ignore_ascii_case("integrity"))
+                .chain(core::iter::once(&("integrity", digest))),
+        );
+
+        // Keep the raw content of the tag (if any) as-is
+        let content_len = raw_content_len(rest, name);
+
+        Some((
+            format!("<{}{}>{}", name, attributes, &rest[..content_len]),
+            content_len,
+        ))
+    });
+
+    assets[index].1 = html.into_bytes();
+
+    Ok(())
+}
+
+/// Returns a predicate telling whether the asset with the given (source) URI gets fingerprinted
+fn is_fingerprinted(conf: &Configuration) -> anyhow::Result<impl Fn(&str) -> bool> {
+    let fingerprint = conf.fingerprint;
+
+    let excluded = conf
+        .fingerprint_exclude
+        .iter()
+        .map(|glob| matcher(glob))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(move |uri: &str| {
+        fingerprint
+            && !uri.eq_ignore_ascii_case("/index.html")
+            && !excluded.iter().any(|matcher| matcher.is_match(uri))
+    })
+}
+
+fn is_text(uri: &str) -> bool {
+    ["html", "htm", "css", "js", "mjs"].contains(&extension(uri).as_str())
+}
+
+/// Returns the extension of the file name in `uri` in lowercase, or an empty string
+/// if the file name has no extension
+fn extension(uri: &str) -> String {
+    let file_name = uri.rsplit('/').next().unwrap_or(uri);
+
+    file_name
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+/// Returns the forms in which `index.html` might refer to the asset with `uri`:
+/// absolute, relative, and relative to the current directory
+fn references(uri: &str) -> [String; 3] {
+    [uri.to_string(), uri[1..].to_string(), format!(".{}", uri)]
+}
+
+/// Inserts the hash of `data` before the extension of the file name in `uri`
+fn fingerprinted(uri: &str, data: &[u8]) -> String {
+    let digest = Sha256::digest(data);
+
+    let hash = digest[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+
+    let (dir, file_name) = uri.rsplit_once('/').unwrap_or(("", uri));
+
+    match file_name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => {
+            format!("{}/{}-{}.{}", dir, stem, hash, extension)
+        }
+        _ => format!("{}/{}-{}", dir, file_name, hash),
+    }
+}
+
+/// Returns the paths in the text asset `data` at `uri` which might refer to other assets:
+/// the byte range of each path along with the URI it stands for, resolved against the directory
+/// of `uri`.
+///
+/// A path is a whole run of the characters which file names and directories usually consist of,
+/// without the query and the fragment, so `/vendor/app.js` or `myapp.js` never stand for `/app.js`.
+fn paths<'a>(uri: &'a str, data: &'a [u8]) -> impl Iterator<Item = (Range<usize>, String)> + 'a {
+    let is_path_byte = |byte: u8| byte.is_ascii_alphanumeric() || b"_-.~/%+@".contains(&byte);
+
+    let mut index = 0;
+
+    iter::from_fn(move || {
+        while index < data.len() {
+            let start = index;
+
+            index += data[start..]
+                .iter()
+                .take_while(|byte| is_path_byte(**byte))
+                .count()
+                .max(1);
+
+            // A trailing dot ends a sentence rather than a file name
+            let end = start
+                + data[start..index]
+                    .iter()
+                    .rposition(|byte| *byte != b'.')
+                    .map(|last| last + 1)
+                    .unwrap_or(0);
+
+            if start < end && is_path_byte(data[start]) {
+                // The path bytes are ASCII
+                let path = std::str::from_utf8(&data[start..end]).unwrap_or_default();
+
+                if let Some(resolved) = resolve(uri, path) {
+                    return Some((start..end, resolved));
+                }
+            }
+        }
+
+        None
+    })
+}
+
+/// Resolves `path` as referenced from the asset at `uri` to the URI of an asset, unless it refers
+/// to another host or out of the assets directory
+fn resolve(uri: &str, path: &str) -> Option<String> {
+    if path.starts_with("//") {
+        return None;
+    }
+
+    let dir = if path.starts_with('/') {
+        ""
+    } else {
+        uri.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("")
+    };
+
+    let mut segments = Vec::new();
+
+    for segment in dir.split('/').chain(path.split('/')) {
+        match segment {
+            "" | "." => (),
+            ".." => {
+                segments.pop()?;
+            }
+            segment => segments.push(segment),
+        }
+    }
+
+    Some(format!("/{}", segments.join("/")))
+}
+
+/// Whether the text asset `data` at `uri` refers to the asset at `other`
+fn contains_reference(uri: &str, data: &[u8], other: &str) -> bool {
+    paths(uri, data).any(|(_, resolved)| resolved == other)
+}
+
+/// Rewrites the references in the text asset `data` at `uri` to the assets renamed by `renames`,
+/// keeping their relative or absolute form
+fn replace_references(uri: &str, data: &[u8], renames: &BTreeMap<String, String>) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len());
+    let mut index = 0;
+
+    for (range, resolved) in paths(uri, data) {
+        let Some(new_uri) = renames.get(&resolved) else {
+            continue;
+        };
+
+        // Renaming only changes the file name, so the directory part of the path stays as it is
+        let path = &data[range.clone()];
+        let file_name_start = range.start
+            + path
+                .iter()
+                .rposition(|byte| *byte == b'/')
+                .map(|slash| slash + 1)
+                .unwrap_or(0);
+
+        let new_file_name = new_uri.rsplit('/').next().unwrap_or(new_uri);
+
+        result.extend_from_slice(&data[index..file_name_start]);
+        result.extend_from_slice(new_file_name.as_bytes());
+
+        index = range.end;
+    }
+
+    result.extend_from_slice(&data[index..]);
+
+    result
+}
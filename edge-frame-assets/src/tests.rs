@@ -0,0 +1,496 @@
+use crate::AssetMetadata;
+
+fn derived(uri: &str) -> (&str, Option<&str>, Option<&str>, Option<&str>) {
+    let metadata = AssetMetadata::derive(uri);
+
+    assert_eq!(metadata.etag, None);
+    assert!(metadata.headers.is_empty());
+
+    (
+        metadata.uri,
+        metadata.cache_control,
+        metadata.content_encoding,
+        metadata.content_type,
+    )
+}
+
+const LONG: Option<&str> = Some("public, max-age=31536000");
+const NO_CACHE: Option<&str> = Some("no-cache");
+
+#[test]
+fn derive_content_type() {
+    assert_eq!(
+        derived("/app.js"),
+        ("/app.js", LONG, None, Some("text/javascript"))
+    );
+    assert_eq!(
+        derived("/style.css"),
+        ("/style.css", LONG, None, Some("text/css"))
+    );
+    assert_eq!(
+        derived("/app_bg.wasm"),
+        ("/app_bg.wasm", LONG, None, Some("application/wasm"))
+    );
+    assert_eq!(
+        derived("/img/logo.png"),
+        ("/img/logo.png", LONG, None, Some("image/png"))
+    );
+    assert_eq!(
+        derived("/about.html"),
+        ("/about.html", LONG, None, Some("text/html"))
+    );
+}
+
+#[test]
+fn derive_content_type_of_last_extension() {
+    assert_eq!(
+        derived("/app.min.js"),
+        ("/app.min.js", LONG, None, Some("text/javascript"))
+    );
+}
+
+#[test]
+fn derive_content_type_case_insensitive() {
+    assert_eq!(
+        derived("/LOGO.PNG"),
+        ("/LOGO.PNG", LONG, None, Some("image/png"))
+    );
+}
+
+#[test]
+fn derive_unknown_suffix() {
+    assert_eq!(derived("/data.xyz"), ("/data.xyz", LONG, None, None));
+}
+
+#[test]
+fn derive_no_suffix() {
+    assert_eq!(derived("/LICENSE"), ("/LICENSE", LONG, None, None));
+
+    // Dots in the directories do not make an extension
+    assert_eq!(
+        derived("/v1.2/LICENSE"),
+        ("/v1.2/LICENSE", LONG, None, None)
+    );
+}
+
+#[test]
+fn derive_index() {
+    assert_eq!(
+        derived("/index.html"),
+        ("/", NO_CACHE, None, Some("text/html"))
+    );
+    assert_eq!(
+        derived("/INDEX.HTML"),
+        ("/", NO_CACHE, None, Some("text/html"))
+    );
+
+    // Only the root index page is mapped to `/`
+    assert_eq!(
+        derived("/docs/index.html"),
+        ("/docs/index.html", LONG, None, Some("text/html"))
+    );
+}
+
+#[test]
+fn derive_gzip() {
+    assert_eq!(
+        derived("/app.js.gz"),
+        ("/app.js", LONG, Some("gzip"), Some("text/javascript"))
+    );
+    assert_eq!(
+        derived("/index.html.gz"),
+        ("/", NO_CACHE, Some("gzip"), Some("text/html"))
+    );
+    assert_eq!(
+        derived("/data.xyz.GZ"),
+        ("/data.xyz", LONG, Some("gzip"), None)
+    );
+}
+
+#[test]
+fn derive_brotli() {
+    assert_eq!(
+        derived("/style.css.br"),
+        ("/style.css", LONG, Some("br"), Some("text/css"))
+    );
+    assert_eq!(
+        derived("/index.html.br"),
+        ("/", NO_CACHE, Some("br"), Some("text/html"))
+    );
+}
+
+#[test]
+fn derive_encoding_without_extension() {
+    assert_eq!(
+        derived("/archive.gz"),
+        ("/archive", LONG, Some("gzip"), None)
+    );
+}
+
+#[test]
+fn derive_with_mime_types() {
+    let mime_types = [
+        ("xyz", "application/x-xyz"),
+        ("js", "application/javascript"),
+    ];
+
+    let metadata = AssetMetadata::derive_with_mime_types("/data.xyz", &mime_types);
+    assert_eq!(metadata.content_type, Some("application/x-xyz"));
+
+    // The given MIME types take precedence over the built-in ones
+    let metadata = AssetMetadata::derive_with_mime_types("/app.js.gz", &mime_types);
+    assert_eq!(metadata.uri, "/app.js");
+    assert_eq!(metadata.content_encoding, Some("gzip"));
+    assert_eq!(metadata.content_type, Some("application/javascript"));
+
+    // The built-in MIME types still apply to the other extensions
+    let metadata = AssetMetadata::derive_with_mime_types("/style.css", &mime_types);
+    assert_eq!(metadata.content_type, Some("text/css"));
+}
+
+#[cfg(feature = "prepare")]
+mod prepare {
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::io::Read;
+    use std::path::Path;
+
+    use flate2::read::GzDecoder;
+
+    use crate::pack::{AssetPack, PackError, HEADER_LEN};
+    use crate::prepare::{self, Configuration};
+
+    /// Calls `f` with a scratch directory, which has an `assets` directory holding `files`
+    fn with_assets<T>(files: &[(&str, &[u8])], f: impl FnOnce(&Path) -> T) -> T {
+        prepare::with_temp_dir("edge-frame-assets-test", |dir| {
+            for (uri, data) in files {
+                let path = dir.join("assets").join(&uri[1..]);
+
+                fs::create_dir_all(path.parent().unwrap())?;
+                fs::write(path, data)?;
+            }
+
+            Ok(f(dir))
+        })
+        .unwrap()
+    }
+
+    /// Runs `prepare::compress` on an assets directory with `files`, and returns the URI,
+    /// the content encoding and the data of each resulting file
+    fn output(
+        files: &[(&str, &[u8])],
+        conf: &Configuration,
+    ) -> Vec<(String, Option<&'static str>, Vec<u8>)> {
+        with_assets(files, |dir| {
+            let output_files =
+                prepare::compress(dir.join("assets"), dir.join("out"), conf, |_| ()).unwrap();
+
+            output_files
+                .into_iter()
+                .map(|output_file| {
+                    let data = fs::read(&output_file.path).unwrap();
+
+                    (output_file.uri, output_file.content_encoding, data)
+                })
+                .collect()
+        })
+    }
+
+    /// Like `output`, but returns the decoded content of each resulting asset by URI
+    fn compress(files: &[(&str, &[u8])], conf: &Configuration) -> BTreeMap<String, Vec<u8>> {
+        output(files, conf)
+            .into_iter()
+            .map(|(uri, content_encoding, data)| match content_encoding {
+                Some("gzip") => {
+                    let mut decoded = Vec::new();
+                    GzDecoder::new(data.as_slice())
+                        .read_to_end(&mut decoded)
+                        .unwrap();
+
+                    (uri.strip_suffix(".gz").unwrap().to_string(), decoded)
+                }
+                None => (uri, data),
+                Some(other) => panic!("Unexpected content encoding {}", other),
+            })
+            .collect()
+    }
+
+    fn fingerprinted(assets: &BTreeMap<String, Vec<u8>>, prefix: &str, suffix: &str) -> String {
+        let matching = assets
+            .keys()
+            .filter(|uri| {
+                uri.starts_with(prefix)
+                    && uri.ends_with(suffix)
+                    && !uri[prefix.len()..].contains('/')
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(matching.len(), 1, "{:?}", assets.keys());
+
+        matching[0].clone()
+    }
+
+    fn text(assets: &BTreeMap<String, Vec<u8>>, uri: &str) -> String {
+        String::from_utf8(assets[uri].clone()).unwrap()
+    }
+
+    fn fingerprint_conf() -> Configuration {
+        Configuration {
+            fingerprint: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn fingerprint_same_file_names() {
+        let assets = compress(
+            &[
+                (
+                    "/index.html",
+                    b"<script src=\"/app.js\"></script>\
+                      <script src=\"/vendor/app.js\"></script>\
+                      <script src=\"./vendor/app.js?v=1\"></script>\
+                      <script>const name = \"myapp.js\";</script>",
+                ),
+                ("/app.js", b"console.log('root');"),
+                ("/vendor/app.js", b"console.log('vendor');"),
+            ],
+            &fingerprint_conf(),
+        );
+
+        let app = fingerprinted(&assets, "/app-", ".js");
+        let vendor_app = fingerprinted(&assets, "/vendor/app-", ".js");
+
+        assert_ne!(app[1..], vendor_app["/vendor/".len()..]);
+
+        assert_eq!(
+            text(&assets, "/index.html"),
+            format!(
+                "<script src=\"{}\"></script>\
+                 <script src=\"{}\"></script>\
+                 <script src=\".{}?v=1\"></script>\
+                 <script>const name = \"myapp.js\";</script>",
+                app, vendor_app, vendor_app
+            )
+        );
+    }
+
+    #[test]
+    fn fingerprint_relative_to_referrer() {
+        let assets = compress(
+            &[
+                (
+                    "/index.html",
+                    b"<link rel=\"stylesheet\" href=\"fonts/fonts.css\">",
+                ),
+                (
+                    "/fonts/fonts.css",
+                    b"@font-face { src: url(a.woff2); } .logo { background: url(../img/logo.png); }",
+                ),
+                ("/fonts/a.woff2", b"\x00font"),
+                ("/img/logo.png", b"\x89PNG"),
+            ],
+            &fingerprint_conf(),
+        );
+
+        let fonts = fingerprinted(&assets, "/fonts/fonts-", ".css");
+        let font = fingerprinted(&assets, "/fonts/a-", ".woff2");
+        let logo = fingerprinted(&assets, "/img/logo-", ".png");
+
+        assert_eq!(
+            text(&assets, "/index.html"),
+            format!("<link rel=\"stylesheet\" href=\"{}\">", &fonts[1..])
+        );
+
+        assert_eq!(
+            text(&assets, &fonts),
+            format!(
+                "@font-face {{ src: url({}); }} .logo {{ background: url(..{}); }}",
+                &font["/fonts/".len()..],
+                logo
+            )
+        );
+    }
+
+    #[test]
+    fn precompressed_next_to_source() {
+        let script = "console.log('a script compressing well');\n".repeat(64);
+
+        let mut output = output(
+            &[
+                ("/app.js", script.as_bytes()),
+                ("/app.js.gz", b"precompressed elsewhere"),
+            ],
+            &Default::default(),
+        );
+        output.sort();
+
+        assert_eq!(output.len(), 2);
+        assert_eq!(output[0].0, "/app.js.gz");
+        assert_eq!(output[0].1, None);
+        assert_eq!(output[0].2, b"precompressed elsewhere");
+        assert_eq!(output[1].0, "/app.js.gz");
+        assert_eq!(output[1].1, Some("gzip"));
+
+        let mut decoded = Vec::new();
+        GzDecoder::new(output[1].2.as_slice())
+            .read_to_end(&mut decoded)
+            .unwrap();
+
+        assert_eq!(decoded, script.as_bytes());
+    }
+
+    #[test]
+    fn smallest() {
+        let script = "console.log('a script compressing well');\n".repeat(64);
+
+        let encodings = |files: &[(&str, &[u8])], identity| {
+            let conf = Configuration {
+                brotli: true,
+                smallest: true,
+                identity,
+                ..Default::default()
+            };
+
+            let mut encodings = output(files, &conf)
+                .into_iter()
+                .map(|(uri, content_encoding, _)| (uri, content_encoding))
+                .collect::<Vec<_>>();
+            encodings.sort();
+
+            encodings
+        };
+
+        assert_eq!(
+            encodings(&[("/app.js", script.as_bytes())], false),
+            [("/app.js.br".to_string(), Some("br"))]
+        );
+
+        assert_eq!(
+            encodings(&[("/app.js", script.as_bytes())], true),
+            [
+                ("/app.js".to_string(), None),
+                ("/app.js.br".to_string(), Some("br")),
+            ]
+        );
+
+        // Without an encoding making the asset smaller, it is stored as-is
+        assert_eq!(
+            encodings(&[("/a.js", b"a")], false),
+            [("/a.js".to_string(), None)]
+        );
+    }
+
+    /// Runs `prepare::run_pack` on an assets directory with `files`, and returns the pack
+    fn pack(files: &[(&str, &[u8])], conf: &Configuration, version: u32) -> Vec<u8> {
+        with_assets(files, |dir| {
+            let pack_file = dir.join("assets.pack");
+
+            prepare::run_pack(dir.join("assets"), &pack_file, version, conf).unwrap();
+
+            fs::read(pack_file).unwrap()
+        })
+    }
+
+    const PACK_FILES: &[(&str, &[u8])] = &[
+        (
+            "/index.html",
+            b"<!DOCTYPE html><html><script src=\"app.js\"></script></html>",
+        ),
+        ("/app.js", b"console.log('app');"),
+        ("/logo.png", b"\x89PNG"),
+    ];
+
+    fn pack_conf() -> Configuration {
+        Configuration {
+            identity: true,
+            headers: vec![(
+                "/index.html".to_string(),
+                "Content-Security-Policy".to_string(),
+                "default-src 'self'".to_string(),
+            )],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn pack_round_trip() {
+        let packed = pack(PACK_FILES, &pack_conf(), 7);
+
+        // The pack can be followed by unrelated data, e.g. the rest of a flash partition
+        let mut partition = packed.clone();
+        partition.extend_from_slice(&[0xff; 64]);
+
+        for data in [&packed, &partition] {
+            let pack = AssetPack::new(data).unwrap();
+
+            assert_eq!(pack.version(), 7);
+            assert_eq!(pack.len(), pack.entries().count());
+
+            for (uri, data) in PACK_FILES {
+                let uri = if *uri == "/index.html" { "/" } else { uri };
+
+                let entry = pack
+                    .entries()
+                    .find(|entry| entry.uri == uri && entry.content_encoding.is_none())
+                    .unwrap();
+
+                assert_eq!(entry.data, *data, "{}", uri);
+                assert!(entry.etag.is_some());
+            }
+
+            let index = pack.entries().find(|entry| entry.uri == "/").unwrap();
+
+            assert_eq!(index.content_type, Some("text/html"));
+            assert_eq!(index.cache_control, Some("no-cache"));
+            assert_eq!(
+                index.headers().collect::<Vec<_>>(),
+                [("Content-Security-Policy", "default-src 'self'")]
+            );
+
+            // All variants of an asset are next to each other
+            let uris = pack.entries().map(|entry| entry.uri).collect::<Vec<_>>();
+            let mut grouped = uris.clone();
+            grouped.dedup();
+            let mut unique = grouped.clone();
+            unique.sort();
+            unique.dedup();
+
+            assert_eq!(grouped.len(), unique.len(), "{:?}", uris);
+        }
+    }
+
+    #[test]
+    fn pack_checksum() {
+        let packed = pack(PACK_FILES, &pack_conf(), 1);
+
+        // Flip a byte of the index, then one of the data
+        for position in [HEADER_LEN, packed.len() - 1] {
+            let mut corrupt = packed.clone();
+            corrupt[position] ^= 0x01;
+
+            assert_eq!(
+                AssetPack::new(&corrupt).err(),
+                Some(PackError::Checksum),
+                "{}",
+                position
+            );
+        }
+    }
+
+    #[test]
+    fn pack_truncated() {
+        let packed = pack(PACK_FILES, &pack_conf(), 1);
+
+        for len in [packed.len() - 1, HEADER_LEN + 1, 10] {
+            assert_eq!(
+                AssetPack::new(&packed[..len]).err(),
+                Some(PackError::Truncated),
+                "{}",
+                len
+            );
+        }
+
+        assert_eq!(AssetPack::new(b"EFA").err(), Some(PackError::NotAPack));
+    }
+}
@@ -0,0 +1,77 @@
+use std::cell::RefCell;
+use std::env;
+use std::path::{Path, PathBuf};
+
+use proc_macro::{Literal, TokenStream};
+
+use syn::{parse_macro_input, LitStr};
+
+use edge_frame_assets::prepare;
+
+/// Compresses the assets in the given directory (relative to the directory of the crate manifest)
+/// at compile time and expands to their table, an `edge_frame::assets::serve::Assets` value.
+///
+/// The assets are prepared with the default `edge_frame::assets::prepare::Configuration`.
+///
+/// The compiler only expands the macro again when one of the files embedded by the previous
+/// expansion changes, so a file added to (or removed from) the directory is only picked up once
+/// something else triggers a rebuild of the crate, e.g. `touch`-ing the source file invoking the macro.
+#[proc_macro]
+pub fn assets(input: TokenStream) -> TokenStream {
+    let assets_dir = parse_macro_input!(input as LitStr);
+
+    match embed(&assets_dir.value()) {
+        Ok(assets) => assets,
+        Err(err) => syn::Error::new(assets_dir.span(), format!("{:#}", err))
+            .to_compile_error()
+            .into(),
+    }
+}
+
+fn embed(assets_dir: &str) -> anyhow::Result<TokenStream> {
+    let assets_dir = PathBuf::from(
+        env::var_os("CARGO_MANIFEST_DIR")
+            .ok_or_else(|| anyhow::anyhow!("CARGO_MANIFEST_DIR variable is not defined"))?,
+    )
+    .join(assets_dir);
+
+    // The compressed files are only needed until they are embedded into the table
+    prepare::with_temp_dir("edge-frame-assets", |output_dir| {
+        expand(&assets_dir, output_dir)
+    })
+}
+
+fn expand(assets_dir: &Path, output_dir: &Path) -> anyhow::Result<TokenStream> {
+    let conf = prepare::Configuration::default();
+
+    let source_files = RefCell::new(Vec::new());
+
+    let output_files = prepare::compress(assets_dir, output_dir, &conf, |path| {
+        if path.starts_with(assets_dir) && path.is_file() {
+            source_files.borrow_mut().push(path.to_owned());
+        }
+    })?;
+
+    let table = prepare::table(&output_files, &conf, |_, data| {
+        Literal::byte_string(data).to_string()
+    })?;
+
+    // Referring to the source files makes the compiler expand the macro again when they change
+    let source_files = source_files
+        .into_inner()
+        .iter()
+        .map(|source_file| {
+            format!(
+                "const _: &[u8] = include_bytes!({:?});\n",
+                source_file.display().to_string()
+            )
+        })
+        .collect::<String>();
+
+    format!(
+        "{{\n{}use ::edge_frame::assets::serve::AssetMetadata;\n{}}}",
+        source_files, table
+    )
+    .parse()
+    .map_err(|err| anyhow::anyhow!("Cannot parse the asset table: {}", err))
+}
@@ -1,322 +1,1116 @@
-pub const MAX_ASSETS: usize = 10;
+pub use edge_frame_assets::{pack, AssetMetadata, MIME_TYPES};
+
+#[cfg(feature = "assets-prepare")]
+pub use edge_frame_assets::prepare;
 
 #[cfg(feature = "assets-serve")]
 pub mod serve {
-    use core::fmt::Debug;
+    use core::fmt::Write as _;
+
+    use log::warn;
 
-    use embedded_svc::http::server::{Connection, Request};
+    use embedded_svc::http::server::{Connection, Handler, Middleware, Request};
+    use embedded_svc::http::{Method, Query};
     use embedded_svc::utils::http::Headers;
 
     use embedded_svc::io::Write;
 
-    pub type Asset = (&'static str, &'static [u8]);
+    pub use super::pack::{AssetPack, PackEntry, PackError};
+    pub use super::AssetMetadata;
 
-    pub type Assets = [Asset; super::MAX_ASSETS];
+    pub type Asset = (AssetMetadata<'static>, &'static [u8]);
 
+    pub type Assets = &'static [Asset];
+
+    /// Expands to the asset table generated by `prepare::run` (or `prepare::run_with_conf`)
+    /// in `build.rs` for `$module`.
+    ///
+    /// See also `assets!`, which embeds the assets without a build script.
     #[macro_export]
-    macro_rules! assets {
-        ($module:literal) => {
-            [
-                (
-                    env!(concat!($module, "_EDGE_FRAME_ASSET_URI_0")),
-                    include_bytes!(env!(concat!($module, "_EDGE_FRAME_ASSET_DATA_0"))),
-                ),
-                (
-                    env!(concat!($module, "_EDGE_FRAME_ASSET_URI_1")),
-                    include_bytes!(env!(concat!($module, "_EDGE_FRAME_ASSET_DATA_1"))),
-                ),
-                (
-                    env!(concat!($module, "_EDGE_FRAME_ASSET_URI_2")),
-                    include_bytes!(env!(concat!($module, "_EDGE_FRAME_ASSET_DATA_2"))),
-                ),
-                (
-                    env!(concat!($module, "_EDGE_FRAME_ASSET_URI_3")),
-                    include_bytes!(env!(concat!($module, "_EDGE_FRAME_ASSET_DATA_3"))),
-                ),
-                (
-                    env!(concat!($module, "_EDGE_FRAME_ASSET_URI_4")),
-                    include_bytes!(env!(concat!($module, "_EDGE_FRAME_ASSET_DATA_4"))),
-                ),
-                (
-                    env!(concat!($module, "_EDGE_FRAME_ASSET_URI_5")),
-                    include_bytes!(env!(concat!($module, "_EDGE_FRAME_ASSET_DATA_5"))),
-                ),
-                (
-                    env!(concat!($module, "_EDGE_FRAME_ASSET_URI_6")),
-                    include_bytes!(env!(concat!($module, "_EDGE_FRAME_ASSET_DATA_6"))),
-                ),
-                (
-                    env!(concat!($module, "_EDGE_FRAME_ASSET_URI_7")),
-                    include_bytes!(env!(concat!($module, "_EDGE_FRAME_ASSET_DATA_7"))),
-                ),
-                (
-                    env!(concat!($module, "_EDGE_FRAME_ASSET_URI_8")),
-                    include_bytes!(env!(concat!($module, "_EDGE_FRAME_ASSET_DATA_8"))),
-                ),
-                (
-                    env!(concat!($module, "_EDGE_FRAME_ASSET_URI_9")),
-                    include_bytes!(env!(concat!($module, "_EDGE_FRAME_ASSET_DATA_9"))),
-                ),
-            ]
-        };
+    macro_rules! prepared_assets {
+        ($module:literal) => {{
+            use $crate::assets::serve::AssetMetadata;
+
+            include!(env!(concat!($module, "_EDGE_FRAME_ASSETS")))
+        }};
+    }
+
+    /// The default maximum number of bytes written to the connection at once
+    pub const DEFAULT_CHUNK_SIZE: usize = 2048;
+
+    #[derive(Debug, Clone)]
+    pub struct Configuration {
+        /// The maximum number of bytes written to the connection at once.
+        /// The async serve path yields to other tasks between chunks.
+        pub chunk_size: usize,
+        /// Abandon the response if writing its body takes longer than this, so that a stalled
+        /// client does not block the server. The response then fails with
+        /// `ServeError::WriteTimeout`, so that the server closes the connection rather than
+        /// leaving the client with a truncated body.
+        ///
+        /// The blocking serve path can only check the timeout between chunks, so a single
+        /// stalled write still blocks until the connection gives up; the async serve path
+        /// abandons the write in progress.
+        #[cfg(feature = "assets-serve-timeout")]
+        pub write_timeout: Option<embassy_time::Duration>,
+        /// Headers sent with every asset, e.g. security headers like `Content-Security-Policy`
+        /// or `X-Content-Type-Options: nosniff`
+        pub headers: &'static [(&'static str, &'static str)],
+    }
+
+    impl Configuration {
+        pub const fn new() -> Self {
+            Self {
+                chunk_size: DEFAULT_CHUNK_SIZE,
+                #[cfg(feature = "assets-serve-timeout")]
+                write_timeout: None,
+                headers: &[],
+            }
+        }
+    }
+
+    impl Default for Configuration {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    /// The error of serving an asset
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ServeError<E> {
+        /// Writing to the connection failed
+        Io(E),
+        /// Writing the body took longer than `Configuration::write_timeout`,
+        /// so the response is truncated
+        WriteTimeout,
+    }
+
+    impl<E: core::fmt::Display> core::fmt::Display for ServeError<E> {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            match self {
+                Self::Io(err) => write!(f, "IO error: {}", err),
+                Self::WriteTimeout => write!(f, "Write timeout"),
+            }
+        }
+    }
+
+    impl<E: embedded_svc::io::Error> embedded_svc::io::Error for ServeError<E> {
+        fn kind(&self) -> embedded_svc::io::ErrorKind {
+            match self {
+                Self::Io(err) => err.kind(),
+                Self::WriteTimeout => embedded_svc::io::ErrorKind::TimedOut,
+            }
+        }
+    }
+
+    /// Serves a whole asset table (or, as an `AssetPackHandler`, an asset pack), as required
+    /// by single-page apps.
+    ///
+    /// Requests for unknown paths which do not look like assets (i.e. which have no file
+    /// extension, like the routes of `yew-router`) are answered with the fallback asset
+    /// (`/index.html` by default), so that app routes survive a page reload.
+    ///
+    /// The other misses are answered with the not found page (e.g. `/404.html`) if there is one,
+    /// and with a bare 404 status otherwise. As a `Middleware`, the handler answers the requests
+    /// which the composed handler fails to respond to with the error page (e.g. `/500.html`)
+    /// and a 500 status.
+    #[derive(Debug, Clone)]
+    pub struct AssetsHandler<S = Assets> {
+        assets: S,
+        fallback: Option<&'static str>,
+        not_found: Option<&'static str>,
+        error: Option<&'static str>,
+        conf: Configuration,
+    }
+
+    /// Serves the assets of an asset pack, the same way `AssetsHandler` serves an asset table
+    pub type AssetPackHandler = AssetsHandler<AssetPack<'static>>;
+
+    impl<S: Copy> AssetsHandler<S> {
+        pub const fn new(assets: S) -> Self {
+            Self {
+                assets,
+                fallback: Some("/"),
+                not_found: None,
+                error: None,
+                conf: Configuration::new(),
+            }
+        }
+
+        pub const fn with_fallback(self, fallback: Option<&'static str>) -> Self {
+            Self { fallback, ..self }
+        }
+
+        /// The asset served with a 404 status when no asset matches the request
+        pub const fn with_not_found(self, not_found: Option<&'static str>) -> Self {
+            Self { not_found, ..self }
+        }
+
+        /// The asset served with a 500 status when the handler composed with this one fails
+        pub const fn with_error(self, error: Option<&'static str>) -> Self {
+            Self { error, ..self }
+        }
+
+        pub const fn with_conf(self, conf: Configuration) -> Self {
+            Self { conf, ..self }
+        }
+    }
+
+    impl AssetsHandler {
+        pub fn lookup(&self, uri: &str) -> &'static [Asset] {
+            lookup(self.assets, self.fallback, uri)
+        }
+
+        /// Returns the variants of the page for `status`, if the handler has one
+        pub fn lookup_status(&self, status: u16) -> &'static [Asset] {
+            status_page(self.not_found, self.error, status)
+                .map(|page| variants(self.assets, normalize(page)))
+                .unwrap_or(&[])
+        }
+    }
+
+    impl AssetPackHandler {
+        pub fn lookup(&self, uri: &str) -> heapless::Vec<PackEntry<'static>, MAX_PACK_VARIANTS> {
+            let path = normalize(uri);
+
+            let found = pack_variants(&self.assets, path);
+
+            match self.fallback {
+                Some(fallback) if found.is_empty() && !has_extension(path) => {
+                    pack_variants(&self.assets, normalize(fallback))
+                }
+                _ => found,
+            }
+        }
+
+        /// Returns the entries of the page for `status`, if the handler has one
+        pub fn lookup_status(
+            &self,
+            status: u16,
+        ) -> heapless::Vec<PackEntry<'static>, MAX_PACK_VARIANTS> {
+            status_page(self.not_found, self.error, status)
+                .map(|page| pack_variants(&self.assets, normalize(page)))
+                .unwrap_or_default()
+        }
+    }
+
+    impl<C: Connection> Handler<C> for AssetsHandler {
+        type Error = ServeError<C::Error>;
+
+        fn handle(&self, connection: &mut C) -> Result<(), Self::Error> {
+            let request = Request::wrap(connection);
+
+            let variants = self.lookup(request.uri());
+
+            if variants.is_empty() {
+                serve_status_variants(request, self.lookup_status(404), 404, &self.conf)
+            } else {
+                serve_variants(request, variants, &self.conf)
+            }
+        }
+    }
+
+    impl<C, H> Middleware<C, H> for AssetsHandler
+    where
+        C: Connection,
+        H: Handler<C>,
+    {
+        type Error = H::Error;
+
+        fn handle(&self, connection: &mut C, handler: &H) -> Result<(), Self::Error> {
+            let result = handler.handle(connection);
+
+            if result.is_err() && !connection.is_response_initiated() {
+                let variants = self.lookup_status(500);
+
+                if serve_status_variants(Request::wrap(connection), variants, 500, &self.conf)
+                    .is_err()
+                {
+                    warn!("Serving the error page failed");
+                }
+            }
+
+            result
+        }
+    }
+
+    /// The maximum number of variants of an asset in an asset pack
+    pub const MAX_PACK_VARIANTS: usize = 4;
+
+    impl<C: Connection> Handler<C> for AssetPackHandler {
+        type Error = ServeError<C::Error>;
+
+        fn handle(&self, connection: &mut C) -> Result<(), Self::Error> {
+            let request = Request::wrap(connection);
+
+            let variants = self.lookup(request.uri());
+
+            if variants.is_empty() {
+                serve_status_pack_variants(request, &self.lookup_status(404), 404, &self.conf)
+            } else {
+                serve_pack_variants(request, &variants, &self.conf)
+            }
+        }
+    }
+
+    impl<C, H> Middleware<C, H> for AssetPackHandler
+    where
+        C: Connection,
+        H: Handler<C>,
+    {
+        type Error = H::Error;
+
+        fn handle(&self, connection: &mut C, handler: &H) -> Result<(), Self::Error> {
+            let result = handler.handle(connection);
+
+            if result.is_err() && !connection.is_response_initiated() {
+                let variants = self.lookup_status(500);
+
+                if serve_status_pack_variants(Request::wrap(connection), &variants, 500, &self.conf)
+                    .is_err()
+                {
+                    warn!("Serving the error page failed");
+                }
+            }
+
+            result
+        }
+    }
+
+    /// Returns the entries of `pack` for `uri`
+    pub fn pack_variants<'a>(
+        pack: &AssetPack<'a>,
+        uri: &str,
+    ) -> heapless::Vec<PackEntry<'a>, MAX_PACK_VARIANTS> {
+        pack.entries()
+            .skip_while(|entry| entry.uri != uri)
+            .take_while(|entry| entry.uri == uri)
+            .take(MAX_PACK_VARIANTS)
+            .collect()
+    }
+
+    pub fn serve<C: Connection>(
+        request: Request<C>,
+        asset: Asset,
+    ) -> Result<(), ServeError<C::Error>> {
+        serve_asset_data(request, asset.0, asset.1)
+    }
+
+    pub fn serve_variants<C: Connection>(
+        request: Request<C>,
+        variants: &[Asset],
+        conf: &Configuration,
+    ) -> Result<(), ServeError<C::Error>> {
+        serve_variants_with_status(request, variants, None, conf)
+    }
+
+    /// Serves the variants of a page (e.g. `/404.html`) with `status` rather than as a resource:
+    /// conditional and range requests do not apply, and the response is not cached.
+    ///
+    /// If there are no variants, the response only carries the status.
+    pub fn serve_status_variants<C: Connection>(
+        request: Request<C>,
+        variants: &[Asset],
+        status: u16,
+        conf: &Configuration,
+    ) -> Result<(), ServeError<C::Error>> {
+        serve_variants_with_status(request, variants, Some(status), conf)
     }
 
-    pub fn serve<C: Connection>(request: Request<C>, asset: Asset) -> Result<(), C::Error> {
-        serve_asset_data(request, AssetMetadata::derive(asset.0), asset.1)
+    pub fn serve_pack_variants<C: Connection>(
+        request: Request<C>,
+        variants: &[PackEntry<'static>],
+        conf: &Configuration,
+    ) -> Result<(), ServeError<C::Error>> {
+        serve_variants_with_status(request, variants, None, conf)
+    }
+
+    /// Same as `serve_status_variants`, for the entries of an asset pack
+    pub fn serve_status_pack_variants<C: Connection>(
+        request: Request<C>,
+        variants: &[PackEntry<'static>],
+        status: u16,
+        conf: &Configuration,
+    ) -> Result<(), ServeError<C::Error>> {
+        serve_variants_with_status(request, variants, Some(status), conf)
+    }
+
+    /// Serves the variant accepted by the client, with `status` if it is a page; without any
+    /// variants, the response only carries `status`, or 404
+    fn serve_variants_with_status<C: Connection, V: Variant>(
+        request: Request<C>,
+        variants: &[V],
+        status: Option<u16>,
+        conf: &Configuration,
+    ) -> Result<(), ServeError<C::Error>> {
+        let Some(variant) = select(request.header("Accept-Encoding"), variants) else {
+            request
+                .into_status_response(status.unwrap_or(404))
+                .map_err(ServeError::Io)?;
+
+            return Ok(());
+        };
+
+        let vary = variants.len() > 1 || variant.content_encoding().is_some();
+
+        let mut headers = heapless::Vec::new();
+
+        serve_response(
+            request,
+            &variant.metadata(&mut headers),
+            variant.data(),
+            vary,
+            status,
+            conf,
+        )
     }
 
     pub fn serve_asset_data<C: Connection>(
         request: Request<C>,
         asset_metadata: AssetMetadata<'static>,
         data: &'static [u8],
-    ) -> Result<(), C::Error> {
-        let mut headers = Headers::<4>::new();
+    ) -> Result<(), ServeError<C::Error>> {
+        serve_asset_data_with_conf(request, asset_metadata, data, &Configuration::new())
+    }
 
-        let mut content_len_buf = heapless::String::<20>::new();
-        headers.set_content_len(data.len() as _, &mut content_len_buf);
+    pub fn serve_asset_data_with_conf<C: Connection>(
+        request: Request<C>,
+        asset_metadata: AssetMetadata<'static>,
+        data: &'static [u8],
+        conf: &Configuration,
+    ) -> Result<(), ServeError<C::Error>> {
+        let vary = asset_metadata.content_encoding.is_some();
 
-        if let Some(cache_control) = &asset_metadata.cache_control {
-            headers.set_cache_control(cache_control);
-        }
+        serve_response(request, &asset_metadata, data, vary, None, conf)
+    }
 
-        if let Some(content_encoding) = &asset_metadata.content_encoding {
-            headers.set_content_encoding(content_encoding);
-        }
+    fn serve_response<C: Connection>(
+        request: Request<C>,
+        asset_metadata: &AssetMetadata<'_>,
+        data: &'static [u8],
+        vary: bool,
+        status: Option<u16>,
+        conf: &Configuration,
+    ) -> Result<(), ServeError<C::Error>> {
+        let mut buffers = HeaderBuffers::new();
 
-        if let Some(content_type) = &asset_metadata.content_type {
-            headers.set_content_type(content_type);
-        }
+        let (status, headers, data) = respond(
+            &RequestInfo::new(&request),
+            asset_metadata,
+            data,
+            vary,
+            status,
+            conf.headers,
+            &mut buffers,
+        );
 
-        let mut response = request.into_response(200, None, headers.as_slice())?;
+        let mut response = request
+            .into_response(status, None, headers.as_slice())
+            .map_err(ServeError::Io)?;
 
-        response.write_all(data)?;
+        #[cfg(feature = "assets-serve-timeout")]
+        let started = embassy_time::Instant::now();
+
+        for chunk in data.chunks(conf.chunk_size.max(1)) {
+            #[cfg(feature = "assets-serve-timeout")]
+            if conf
+                .write_timeout
+                .map(|timeout| started.elapsed() > timeout)
+                .unwrap_or(false)
+            {
+                warn!("Write timeout, abandoning the response");
+
+                return Err(ServeError::WriteTimeout);
+            }
+
+            response.write_all(chunk).map_err(ServeError::Io)?;
+        }
 
         Ok(())
     }
 
     pub mod asynch {
-        use embedded_svc::http::server::asynch::{Connection, Handler, Request};
-        use embedded_svc::utils::http::Headers;
+        use embedded_svc::http::server::asynch::{Connection, Handler, Middleware, Request};
 
         use embedded_svc::io::asynch::Write;
 
-        pub use super::{Asset, AssetMetadata};
+        use log::warn;
 
-        pub struct AssetHandler(AssetMetadata<'static>, &'static [u8]);
+        use super::{respond, select, HeaderBuffers, RequestInfo, Variant};
+
+        pub use super::{
+            normalize, pack_variants, variants, Asset, AssetMetadata, AssetPack, AssetPackHandler,
+            AssetsHandler, Configuration, PackEntry, PackError, ServeError, DEFAULT_CHUNK_SIZE,
+            MAX_HEADERS, MAX_PACK_VARIANTS,
+        };
+
+        pub struct AssetHandler(AssetMetadata<'static>, &'static [u8], Configuration);
 
         impl AssetHandler {
             pub const fn new(metadata: AssetMetadata<'static>, data: &'static [u8]) -> Self {
-                Self(metadata, data)
+                Self(metadata, data, Configuration::new())
             }
 
             pub fn from_asset(asset: Asset) -> Self {
-                Self(AssetMetadata::derive(asset.0), asset.1)
+                Self::new(asset.0, asset.1)
+            }
+
+            pub const fn with_conf(self, conf: Configuration) -> Self {
+                Self(self.0, self.1, conf)
             }
         }
 
         impl<C: Connection> Handler<C> for AssetHandler {
-            type Error = C::Error;
+            type Error = ServeError<C::Error>;
+
+            async fn handle(&self, connection: &mut C) -> Result<(), Self::Error> {
+                serve_asset_data_with_conf(
+                    Request::wrap(connection),
+                    self.0.clone(),
+                    self.1,
+                    &self.2,
+                )
+                .await
+            }
+        }
+
+        impl<C: Connection> Handler<C> for AssetsHandler {
+            type Error = ServeError<C::Error>;
+
+            async fn handle(&self, connection: &mut C) -> Result<(), Self::Error> {
+                let request = Request::wrap(connection);
+
+                let variants = self.lookup(request.uri());
+
+                if variants.is_empty() {
+                    serve_status_variants(request, self.lookup_status(404), 404, &self.conf).await
+                } else {
+                    serve_variants(request, variants, &self.conf).await
+                }
+            }
+        }
+
+        impl<C, H> Middleware<C, H> for AssetsHandler
+        where
+            C: Connection,
+            H: Handler<C>,
+        {
+            type Error = H::Error;
+
+            async fn handle(&self, connection: &mut C, handler: &H) -> Result<(), Self::Error> {
+                let result = handler.handle(connection).await;
+
+                if result.is_err() && !connection.is_response_initiated() {
+                    let variants = self.lookup_status(500);
+
+                    if serve_status_variants(Request::wrap(connection), variants, 500, &self.conf)
+                        .await
+                        .is_err()
+                    {
+                        warn!("Serving the error page failed");
+                    }
+                }
+
+                result
+            }
+        }
+
+        impl<C: Connection> Handler<C> for AssetPackHandler {
+            type Error = ServeError<C::Error>;
 
             async fn handle(&self, connection: &mut C) -> Result<(), Self::Error> {
-                serve_asset_data(Request::wrap(connection), self.0.clone(), &self.1).await
+                let request = Request::wrap(connection);
+
+                let variants = self.lookup(request.uri());
+
+                if variants.is_empty() {
+                    serve_status_pack_variants(request, &self.lookup_status(404), 404, &self.conf)
+                        .await
+                } else {
+                    serve_pack_variants(request, &variants, &self.conf).await
+                }
+            }
+        }
+
+        impl<C, H> Middleware<C, H> for AssetPackHandler
+        where
+            C: Connection,
+            H: Handler<C>,
+        {
+            type Error = H::Error;
+
+            async fn handle(&self, connection: &mut C, handler: &H) -> Result<(), Self::Error> {
+                let result = handler.handle(connection).await;
+
+                if result.is_err() && !connection.is_response_initiated() {
+                    let variants = self.lookup_status(500);
+
+                    if serve_status_pack_variants(
+                        Request::wrap(connection),
+                        &variants,
+                        500,
+                        &self.conf,
+                    )
+                    .await
+                    .is_err()
+                    {
+                        warn!("Serving the error page failed");
+                    }
+                }
+
+                result
             }
         }
 
         pub async fn serve<C: Connection>(
             request: Request<C>,
             asset: Asset,
-        ) -> Result<(), C::Error> {
-            serve_asset_data(request, AssetMetadata::derive(asset.0), asset.1).await
+        ) -> Result<(), ServeError<C::Error>> {
+            serve_asset_data(request, asset.0, asset.1).await
+        }
+
+        pub async fn serve_variants<C: Connection>(
+            request: Request<C>,
+            variants: &[Asset],
+            conf: &Configuration,
+        ) -> Result<(), ServeError<C::Error>> {
+            serve_variants_with_status(request, variants, None, conf).await
+        }
+
+        pub async fn serve_status_variants<C: Connection>(
+            request: Request<C>,
+            variants: &[Asset],
+            status: u16,
+            conf: &Configuration,
+        ) -> Result<(), ServeError<C::Error>> {
+            serve_variants_with_status(request, variants, Some(status), conf).await
+        }
+
+        pub async fn serve_pack_variants<C: Connection>(
+            request: Request<C>,
+            variants: &[PackEntry<'static>],
+            conf: &Configuration,
+        ) -> Result<(), ServeError<C::Error>> {
+            serve_variants_with_status(request, variants, None, conf).await
+        }
+
+        pub async fn serve_status_pack_variants<C: Connection>(
+            request: Request<C>,
+            variants: &[PackEntry<'static>],
+            status: u16,
+            conf: &Configuration,
+        ) -> Result<(), ServeError<C::Error>> {
+            serve_variants_with_status(request, variants, Some(status), conf).await
+        }
+
+        async fn serve_variants_with_status<C: Connection, V: Variant>(
+            request: Request<C>,
+            variants: &[V],
+            status: Option<u16>,
+            conf: &Configuration,
+        ) -> Result<(), ServeError<C::Error>> {
+            let Some(variant) = select(request.header("Accept-Encoding"), variants) else {
+                request
+                    .into_status_response(status.unwrap_or(404))
+                    .await
+                    .map_err(ServeError::Io)?;
+
+                return Ok(());
+            };
+
+            let vary = variants.len() > 1 || variant.content_encoding().is_some();
+
+            let mut headers = heapless::Vec::new();
+
+            serve_response(
+                request,
+                &variant.metadata(&mut headers),
+                variant.data(),
+                vary,
+                status,
+                conf,
+            )
+            .await
         }
 
         pub async fn serve_asset_data<C: Connection>(
             request: Request<C>,
             asset_metadata: AssetMetadata<'static>,
             data: &'static [u8],
-        ) -> Result<(), C::Error> {
-            let mut headers = Headers::<4>::new();
+        ) -> Result<(), ServeError<C::Error>> {
+            serve_asset_data_with_conf(request, asset_metadata, data, &Configuration::new()).await
+        }
 
-            let mut content_len_buf = heapless::String::<20>::new();
-            headers.set_content_len(data.len() as _, &mut content_len_buf);
+        pub async fn serve_asset_data_with_conf<C: Connection>(
+            request: Request<C>,
+            asset_metadata: AssetMetadata<'static>,
+            data: &'static [u8],
+            conf: &Configuration,
+        ) -> Result<(), ServeError<C::Error>> {
+            let vary = asset_metadata.content_encoding.is_some();
 
-            if let Some(cache_control) = &asset_metadata.cache_control {
-                headers.set_cache_control(cache_control);
-            }
+            serve_response(request, &asset_metadata, data, vary, None, conf).await
+        }
 
-            if let Some(content_encoding) = &asset_metadata.content_encoding {
-                headers.set_content_encoding(content_encoding);
+        async fn serve_response<C: Connection>(
+            request: Request<C>,
+            asset_metadata: &AssetMetadata<'_>,
+            data: &'static [u8],
+            vary: bool,
+            status: Option<u16>,
+            conf: &Configuration,
+        ) -> Result<(), ServeError<C::Error>> {
+            let mut buffers = HeaderBuffers::new();
+
+            let (status, headers, data) = respond(
+                &RequestInfo::new(&request),
+                asset_metadata,
+                data,
+                vary,
+                status,
+                conf.headers,
+                &mut buffers,
+            );
+
+            let mut response = request
+                .into_response(status, None, headers.as_slice())
+                .await
+                .map_err(ServeError::Io)?;
+
+            #[cfg(feature = "assets-serve-timeout")]
+            let deadline = conf
+                .write_timeout
+                .map(|timeout| embassy_time::Instant::now() + timeout);
+
+            for (index, chunk) in data.chunks(conf.chunk_size.max(1)).enumerate() {
+                if index > 0 {
+                    // Give the other handlers a chance to run
+                    embassy_futures::yield_now().await;
+                }
+
+                #[cfg(feature = "assets-serve-timeout")]
+                if let Some(deadline) = deadline {
+                    let Ok(result) =
+                        embassy_time::with_deadline(deadline, response.write_all(chunk)).await
+                    else {
+                        warn!("Write timeout, abandoning the response");
+
+                        return Err(ServeError::WriteTimeout);
+                    };
+
+                    result.map_err(ServeError::Io)?;
+
+                    continue;
+                }
+
+                response.write_all(chunk).await.map_err(ServeError::Io)?;
             }
 
-            if let Some(content_type) = &asset_metadata.content_type {
-                headers.set_content_type(content_type);
+            Ok(())
+        }
+    }
+
+    /// Strips the query and the fragment from `uri` and maps `/index.html` to `/`,
+    /// which is the URI `assets::prepare` gives to the index page.
+    pub fn normalize(uri: &str) -> &str {
+        let path = uri.split(['?', '#']).next().unwrap_or("");
+
+        if path.is_empty() || path.eq_ignore_ascii_case("/index.html") {
+            "/"
+        } else {
+            path
+        }
+    }
+
+    fn lookup<'a>(assets: &'a [Asset], fallback: Option<&str>, uri: &str) -> &'a [Asset] {
+        let path = normalize(uri);
+
+        let found = variants(assets, path);
+
+        match fallback {
+            Some(fallback) if found.is_empty() && !has_extension(path) => {
+                variants(assets, normalize(fallback))
             }
+            _ => found,
+        }
+    }
 
-            let mut response = request.into_response(200, None, headers.as_slice()).await?;
+    fn status_page(
+        not_found: Option<&'static str>,
+        error: Option<&'static str>,
+        status: u16,
+    ) -> Option<&'static str> {
+        match status {
+            404 => not_found,
+            500 => error,
+            _ => None,
+        }
+    }
 
-            response.write_all(data).await?;
+    fn has_extension(path: &str) -> bool {
+        path.rsplit('/')
+            .next()
+            .map(|file_name| file_name.contains('.'))
+            .unwrap_or(false)
+    }
 
-            Ok(())
+    /// Returns the run of consecutive `assets` entries for `uri`.
+    ///
+    /// `assets::prepare` places all encodings of a file next to each other, so the result
+    /// can be passed as-is to `serve_variants`.
+    pub fn variants<'a>(assets: &'a [Asset], uri: &str) -> &'a [Asset] {
+        let Some(start) = assets.iter().position(|asset| asset.0.uri == uri) else {
+            return &[];
+        };
+
+        let len = assets[start..]
+            .iter()
+            .take_while(|asset| asset.0.uri == uri)
+            .count();
+
+        &assets[start..start + len]
+    }
+
+    /// Picks the smallest variant whose content encoding is accepted by the client.
+    ///
+    /// If the client accepts none of them, the first variant is returned so that
+    /// the asset is still served, as it used to be before content negotiation.
+    fn select<'a, V: Variant>(accept_encoding: Option<&str>, variants: &'a [V]) -> Option<&'a V> {
+        variants
+            .iter()
+            .filter(|variant| is_accepted(accept_encoding, variant.content_encoding()))
+            .min_by_key(|variant| variant.data().len())
+            .or_else(|| variants.first())
+    }
+
+    /// A variant of an asset, either from an asset table or from an asset pack
+    trait Variant {
+        fn content_encoding(&self) -> Option<&str>;
+
+        /// Returns the metadata of the variant, storing its headers in `headers` if needed
+        fn metadata<'a>(
+            &'a self,
+            headers: &'a mut heapless::Vec<(&'static str, &'static str), MAX_HEADERS>,
+        ) -> AssetMetadata<'a>;
+
+        fn data(&self) -> &'static [u8];
+    }
+
+    impl Variant for Asset {
+        fn content_encoding(&self) -> Option<&str> {
+            self.0.content_encoding
+        }
+
+        fn metadata<'a>(
+            &'a self,
+            _headers: &'a mut heapless::Vec<(&'static str, &'static str), MAX_HEADERS>,
+        ) -> AssetMetadata<'a> {
+            self.0.clone()
+        }
+
+        fn data(&self) -> &'static [u8] {
+            self.1
         }
     }
 
-    #[derive(Debug, Clone)]
-    pub struct AssetMetadata<'a> {
-        pub uri: &'a str,
-        pub cache_control: Option<&'a str>,
-        pub content_encoding: Option<&'a str>,
-        pub content_type: Option<&'a str>,
+    impl Variant for PackEntry<'static> {
+        fn content_encoding(&self) -> Option<&str> {
+            self.content_encoding
+        }
+
+        fn metadata<'a>(
+            &'a self,
+            headers: &'a mut heapless::Vec<(&'static str, &'static str), MAX_HEADERS>,
+        ) -> AssetMetadata<'a> {
+            PackEntry::metadata(self, headers)
+        }
+
+        fn data(&self) -> &'static [u8] {
+            self.data
+        }
     }
 
-    impl<'a> AssetMetadata<'a> {
-        pub fn derive(uri: &str) -> AssetMetadata<'_> {
-            let mut split = uri.split('.');
+    fn is_accepted(accept_encoding: Option<&str>, content_encoding: Option<&str>) -> bool {
+        let Some(content_encoding) = content_encoding else {
+            // The identity encoding is always acceptable
+            return true;
+        };
 
-            let suffix = split.next_back().unwrap_or("");
+        // Strictly speaking, a missing `Accept-Encoding` header means that any encoding is
+        // acceptable, yet a lot of non-browser HTTP clients do not decode anything they did not ask for
+        let Some(accept_encoding) = accept_encoding else {
+            return false;
+        };
 
-            let (uri, content_encoding) = if suffix.eq_ignore_ascii_case("gz") {
-                (&uri[..uri.len() - 3], Some("gzip"))
-            } else {
-                (uri, None)
-            };
+        accept_encoding.split(',').any(|coding| {
+            let mut params = coding.split(';');
 
-            let (uri, cache_control) = if uri.eq_ignore_ascii_case("/index.html") {
-                ("/", "no-store")
-            } else {
-                (uri, "public, max-age=31536000")
-            };
+            let name = params.next().unwrap_or("").trim();
 
-            let suffix = if content_encoding.is_some() {
-                split.next_back().unwrap_or("")
-            } else {
-                suffix
-            };
+            let matches = name == "*"
+                || name.eq_ignore_ascii_case(content_encoding)
+                || content_encoding.eq_ignore_ascii_case("gzip")
+                    && name.eq_ignore_ascii_case("x-gzip");
 
-            let content_type = if suffix.eq_ignore_ascii_case("html") {
-                Some("text/html")
-            } else if suffix.eq_ignore_ascii_case("css") {
-                Some("text/css")
-            } else if suffix.eq_ignore_ascii_case("js") {
-                Some("text/javascript")
-            } else if suffix.eq_ignore_ascii_case("wasm") {
-                Some("application/wasm")
-            } else {
-                None
-            };
+            let rejected = params.any(|param| {
+                param
+                    .trim()
+                    .strip_prefix("q=")
+                    .map(|q| q.trim().chars().all(|c| c == '0' || c == '.'))
+                    .unwrap_or(false)
+            });
+
+            matches && !rejected
+        })
+    }
+
+    /// The maximum number of response headers, including the ones from `Configuration::headers`
+    /// and `AssetMetadata::headers`
+    pub const MAX_HEADERS: usize = 24;
+
+    /// The number of header slots reserved for the headers computed by `respond`
+    const RESERVED_HEADERS: usize = 8;
+
+    const ALLOWED_METHODS: &str = "GET, HEAD, OPTIONS";
 
-            AssetMetadata {
-                uri,
-                cache_control: Some(cache_control),
-                content_encoding: content_encoding,
-                content_type: content_type,
+    /// The request method and headers which influence how an asset is served
+    struct RequestInfo<'r> {
+        method: Method,
+        if_none_match: Option<&'r str>,
+        if_range: Option<&'r str>,
+        range: Option<&'r str>,
+    }
+
+    impl<'r> RequestInfo<'r> {
+        fn new(request: &'r (impl Query + embedded_svc::http::Headers)) -> Self {
+            Self {
+                method: request.method(),
+                if_none_match: request.header("If-None-Match"),
+                if_range: request.header("If-Range"),
+                range: request.header("Range"),
             }
         }
     }
-}
 
-#[cfg(feature = "assets-prepare")]
-pub mod prepare {
-    use std::{
-        env, fs, io,
-        iter::repeat,
-        path::{Path, PathBuf},
-    };
-
-    use anyhow;
-    use flate2::{write::GzEncoder, Compression};
-
-    pub fn run(module: impl AsRef<str>, assets_dir: impl AsRef<Path>) -> anyhow::Result<()> {
-        let module = module.as_ref();
-        let assets_dir = assets_dir.as_ref();
-
-        let output_dir = PathBuf::new()
-            .join(env::var_os("OUT_DIR")
-                .ok_or_else(|| anyhow::anyhow!("OUT_DIR variable is not defined. You should call this code from a Cargo `build.rs` script"))?)
-            .join("edge_frame_assets")
-            .join(module);
-
-        let output_files = compress(assets_dir, &output_dir, |path| {
-            println!("cargo:rerun-if-changed={}", path.display())
-        })?;
-
-        let output_files = if output_files.len() > super::MAX_ASSETS {
-            anyhow::bail!(
-                "Maximum number of supported assets is {}",
-                super::MAX_ASSETS
-            );
-        } else if output_files.len() < super::MAX_ASSETS {
-            let empty_file = output_dir.join("__empty__");
+    /// Storage for the formatted response header values
+    struct HeaderBuffers {
+        content_len: heapless::String<20>,
+        content_range: heapless::String<72>,
+    }
 
-            fs::File::create(&empty_file)?;
+    impl HeaderBuffers {
+        const fn new() -> Self {
+            Self {
+                content_len: heapless::String::new(),
+                content_range: heapless::String::new(),
+            }
+        }
+    }
 
-            let len = output_files.len();
+    /// Computes the response for an asset, or for a page served with `status` (see `serve_status_variants`)
+    fn respond<'a>(
+        request_info: &RequestInfo<'_>,
+        asset_metadata: &AssetMetadata<'a>,
+        data: &'static [u8],
+        vary: bool,
+        status: Option<u16>,
+        extra_headers: &'static [(&'static str, &'static str)],
+        buffers: &'a mut HeaderBuffers,
+    ) -> (u16, Headers<'a, MAX_HEADERS>, &'static [u8]) {
+        let mut headers = Headers::<MAX_HEADERS>::new();
 
-            output_files
-                .into_iter()
-                .chain(repeat(empty_file).take(super::MAX_ASSETS - len))
-                .collect::<Vec<_>>()
-        } else {
-            output_files
-        };
+        // The headers of the asset take precedence over the headers of the app,
+        // and the headers computed below take precedence over both
+        for (name, value) in extra_headers.iter().chain(asset_metadata.headers) {
+            if headers.as_slice().len() >= MAX_HEADERS - RESERVED_HEADERS
+                && headers.get(name).is_none()
+            {
+                warn!("Too many headers, skipping header {}", name);
+                continue;
+            }
+
+            headers.set(name, value);
+        }
+
+        if let Some(status) = status {
+            // The page stands in for whatever was requested, so it must not be cached as such
+            headers.set_cache_control("no-store");
+
+            if vary {
+                headers.set("Vary", "Accept-Encoding");
+            }
+
+            headers.set_content_len(data.len() as _, &mut buffers.content_len);
+
+            if let Some(content_encoding) = asset_metadata.content_encoding {
+                headers.set_content_encoding(content_encoding);
+            }
 
-        for (index, output_file) in output_files.iter().enumerate() {
-            let file_name = output_file.file_name().unwrap().to_str().unwrap();
+            if let Some(content_type) = asset_metadata.content_type {
+                headers.set_content_type(content_type);
+            }
 
-            if file_name == "__empty__" {
-                println!("cargo:rustc-env={}_EDGE_FRAME_ASSET_URI_{}=", module, index,);
+            let data = if request_info.method == Method::Head {
+                &[]
             } else {
-                println!(
-                    "cargo:rustc-env={}_EDGE_FRAME_ASSET_URI_{}=/{}",
-                    module,
-                    index,
-                    output_file.file_name().unwrap().to_str().unwrap()
-                );
-            }
-
-            println!(
-                "cargo:rustc-env={}_EDGE_FRAME_ASSET_DATA_{}={}",
-                module,
-                index,
-                output_file.display()
-            );
+                data
+            };
+
+            return (status, headers, data);
         }
 
-        Ok(())
-    }
+        match request_info.method {
+            Method::Get | Method::Head => (),
+            Method::Options => {
+                headers.set("Allow", ALLOWED_METHODS);
 
-    pub fn compress(
-        assets_dir: impl AsRef<Path>,
-        output_dir: impl AsRef<Path>,
-        track: impl Fn(&Path),
-    ) -> anyhow::Result<Vec<PathBuf>> {
-        let assets_dir = assets_dir.as_ref();
-        let output_dir = output_dir.as_ref();
+                return (204, headers, &[]);
+            }
+            _ => {
+                headers.set("Allow", ALLOWED_METHODS);
+                headers.set_content_len(0, &mut buffers.content_len);
 
-        let output_files = fs::read_dir(assets_dir)?
-            .filter_map(|file| file.ok())
-            .filter(|file| file.metadata().map(|md| md.is_file()).unwrap_or(false))
-            .map(|file| {
-                track(&file.path());
+                return (405, headers, &[]);
+            }
+        }
 
-                let output_file =
-                    output_dir.join(format!("{}.gz", file.file_name().to_str().unwrap()));
+        if let Some(cache_control) = asset_metadata.cache_control {
+            headers.set_cache_control(cache_control);
+        }
+
+        if let Some(etag) = asset_metadata.etag {
+            headers.set("ETag", etag);
+        }
+
+        if vary {
+            headers.set("Vary", "Accept-Encoding");
+        }
+
+        if is_not_modified(request_info.if_none_match, asset_metadata) {
+            return (304, headers, &[]);
+        }
 
-                track(&output_file);
+        headers.set("Accept-Ranges", "bytes");
 
-                fs::create_dir_all(output_dir).unwrap();
+        let range = request_info
+            .range
+            .filter(|_| is_range_fresh(request_info.if_range, asset_metadata))
+            .and_then(|range| parse_range(range, data.len()));
 
-                io::copy(
-                    &mut fs::File::open(file.path()).unwrap(),
-                    &mut GzEncoder::new(
-                        fs::File::create(&output_file).unwrap(),
-                        Compression::best(),
-                    ),
+        let (status, data) = match range {
+            Some(Ok((start, end))) => {
+                write!(
+                    buffers.content_range,
+                    "bytes {}-{}/{}",
+                    start,
+                    end - 1,
+                    data.len()
                 )
                 .unwrap();
 
-                output_file
-            })
-            .collect::<Vec<_>>();
+                headers.set("Content-Range", &buffers.content_range);
+
+                (206, &data[start..end])
+            }
+            Some(Err(())) => {
+                write!(buffers.content_range, "bytes */{}", data.len()).unwrap();
+
+                headers.set("Content-Range", &buffers.content_range);
+
+                (416, &[] as &[u8])
+            }
+            None => (200, data),
+        };
+
+        headers.set_content_len(data.len() as _, &mut buffers.content_len);
+
+        if status != 416 {
+            if let Some(content_encoding) = asset_metadata.content_encoding {
+                headers.set_content_encoding(content_encoding);
+            }
+
+            if let Some(content_type) = asset_metadata.content_type {
+                headers.set_content_type(content_type);
+            }
+        }
+
+        if request_info.method == Method::Head {
+            // Same headers as for GET, including `Content-Length`, but without the body
+            (status, headers, &[])
+        } else {
+            (status, headers, data)
+        }
+    }
+
+    /// Parses a single-part `Range: bytes=...` header into a `start..end` byte range of the data.
+    ///
+    /// Returns `None` if the header should be ignored (unknown unit, syntax errors or multiple ranges),
+    /// and `Some(Err(()))` if the range cannot be satisfied.
+    fn parse_range(range: &str, len: usize) -> Option<Result<(usize, usize), ()>> {
+        let range = range.trim().strip_prefix("bytes=")?.trim();
+
+        if range.contains(',') {
+            return None;
+        }
+
+        let (start, end) = range.split_once('-')?;
+        let (start, end) = (start.trim(), end.trim());
+
+        let range = if start.is_empty() {
+            // Suffix range: the last `end` bytes
+            let suffix = end.parse::<usize>().ok()?;
 
-        Ok(output_files)
+            (len - suffix.min(len), len)
+        } else {
+            let start = start.parse::<usize>().ok()?;
+
+            let end = if end.is_empty() {
+                len
+            } else {
+                let end = end.parse::<usize>().ok()?;
+
+                if end < start {
+                    return None;
+                }
+
+                end.saturating_add(1).min(len)
+            };
+
+            (start, end)
+        };
+
+        if range.0 < range.1 {
+            Some(Ok(range))
+        } else {
+            Some(Err(()))
+        }
+    }
+
+    /// `If-Range` only lets the range through if the asset still has the given (strong) entity tag
+    fn is_range_fresh(if_range: Option<&str>, asset_metadata: &AssetMetadata<'_>) -> bool {
+        match if_range {
+            Some(if_range) => asset_metadata.etag == Some(if_range.trim()),
+            None => true,
+        }
+    }
+
+    fn is_not_modified(if_none_match: Option<&str>, asset_metadata: &AssetMetadata<'_>) -> bool {
+        let (Some(if_none_match), Some(etag)) = (if_none_match, asset_metadata.etag) else {
+            return false;
+        };
+
+        // `If-None-Match` uses the weak comparison function, so `W/` prefixes are ignored
+        let etag = etag.trim_start_matches("W/");
+
+        if_none_match
+            .split(',')
+            .map(str::trim)
+            .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
     }
 }
+
+#[cfg(all(test, feature = "assets-serve"))]
+mod mock;
+
+#[cfg(all(test, feature = "assets-serve"))]
+mod tests;
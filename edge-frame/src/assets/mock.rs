@@ -0,0 +1,270 @@
+//! An in-memory test double of an HTTP server connection, implementing both the blocking and the async
+//! `embedded_svc::http::server::Connection` traits, so that `assets::serve` can be exercised natively
+
+extern crate std;
+
+use core::convert::Infallible;
+use core::time::Duration;
+
+use std::string::{String, ToString};
+use std::vec::Vec;
+
+use embedded_svc::http::{server, Headers, Method, Query};
+use embedded_svc::io::{self, ErrorType};
+
+/// The request line and the headers of a `MockConnection`
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    method: Method,
+    uri: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Query for MockRequest {
+    fn uri(&self) -> &str {
+        &self.uri
+    }
+
+    fn method(&self) -> Method {
+        self.method
+    }
+}
+
+impl Headers for MockRequest {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// What has been written to a `MockConnection`
+#[derive(Debug, Clone, Default)]
+pub struct MockResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// The length of each write of the body
+    pub writes: Vec<usize>,
+}
+
+impl MockResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A connection with a request without a body, which records the response
+#[derive(Debug, Clone)]
+pub struct MockConnection {
+    request: MockRequest,
+    body: &'static [u8],
+    response: Option<MockResponse>,
+    write_delay: Duration,
+}
+
+impl MockConnection {
+    pub fn new(method: Method, uri: &str) -> Self {
+        Self {
+            request: MockRequest {
+                method,
+                uri: uri.to_string(),
+                headers: Vec::new(),
+            },
+            body: &[],
+            response: None,
+            write_delay: Duration::ZERO,
+        }
+    }
+
+    pub fn get(uri: &str) -> Self {
+        Self::new(Method::Get, uri)
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.request
+            .headers
+            .push((name.to_string(), value.to_string()));
+
+        self
+    }
+
+    /// Makes each write of the body take `write_delay`, like a slow client
+    #[cfg(feature = "assets-serve-timeout")]
+    pub fn with_write_delay(self, write_delay: Duration) -> Self {
+        Self {
+            write_delay,
+            ..self
+        }
+    }
+
+    /// Returns the response, panicking if none was initiated
+    pub fn response(&self) -> &MockResponse {
+        self.response.as_ref().expect("No response was initiated")
+    }
+
+    fn initiate(&mut self, status: u16, headers: &[(&str, &str)]) {
+        assert!(
+            self.response.is_none(),
+            "The response was already initiated"
+        );
+
+        self.response = Some(MockResponse {
+            status,
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            body: Vec::new(),
+            writes: Vec::new(),
+        });
+    }
+
+    fn write_body(&mut self, buf: &[u8]) {
+        let response = self
+            .response
+            .as_mut()
+            .expect("Writing the body before initiating the response");
+
+        response.body.extend_from_slice(buf);
+        response.writes.push(buf.len());
+    }
+}
+
+impl Query for MockConnection {
+    fn uri(&self) -> &str {
+        self.request.uri()
+    }
+
+    fn method(&self) -> Method {
+        self.request.method()
+    }
+}
+
+impl Headers for MockConnection {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.request.header(name)
+    }
+}
+
+impl ErrorType for MockConnection {
+    type Error = Infallible;
+}
+
+impl io::Read for MockConnection {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        io::Read::read(&mut self.body, buf)
+    }
+}
+
+impl io::Write for MockConnection {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        std::thread::sleep(self.write_delay);
+
+        self.write_body(buf);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl io::asynch::Read for MockConnection {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        io::Read::read(self, buf)
+    }
+}
+
+impl io::asynch::Write for MockConnection {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        // Let a timeout of the response interrupt the write
+        #[cfg(feature = "assets-serve-timeout")]
+        embassy_time::Timer::after(embassy_time::Duration::from_micros(
+            self.write_delay.as_micros() as _,
+        ))
+        .await;
+
+        #[cfg(not(feature = "assets-serve-timeout"))]
+        std::thread::sleep(self.write_delay);
+
+        self.write_body(buf);
+
+        Ok(buf.len())
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl server::Connection for MockConnection {
+    type Headers = MockRequest;
+
+    type Read = &'static [u8];
+
+    type RawConnectionError = Infallible;
+
+    type RawConnection = Self;
+
+    fn split(&mut self) -> (&Self::Headers, &mut Self::Read) {
+        (&self.request, &mut self.body)
+    }
+
+    fn initiate_response<'a>(
+        &'a mut self,
+        status: u16,
+        _message: Option<&'a str>,
+        headers: &'a [(&'a str, &'a str)],
+    ) -> Result<(), Self::Error> {
+        self.initiate(status, headers);
+
+        Ok(())
+    }
+
+    fn is_response_initiated(&self) -> bool {
+        self.response.is_some()
+    }
+
+    fn raw_connection(&mut self) -> Result<&mut Self::RawConnection, Self::Error> {
+        Ok(self)
+    }
+}
+
+impl server::asynch::Connection for MockConnection {
+    type Headers = MockRequest;
+
+    type Read = &'static [u8];
+
+    type RawConnectionError = Infallible;
+
+    type RawConnection = Self;
+
+    fn split(&mut self) -> (&Self::Headers, &mut Self::Read) {
+        (&self.request, &mut self.body)
+    }
+
+    async fn initiate_response(
+        &mut self,
+        status: u16,
+        _message: Option<&str>,
+        headers: &[(&str, &str)],
+    ) -> Result<(), Self::Error> {
+        self.initiate(status, headers);
+
+        Ok(())
+    }
+
+    fn is_response_initiated(&self) -> bool {
+        self.response.is_some()
+    }
+
+    fn raw_connection(&mut self) -> Result<&mut Self::RawConnection, Self::Error> {
+        Ok(self)
+    }
+}
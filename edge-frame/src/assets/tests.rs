@@ -0,0 +1,544 @@
+extern crate std;
+
+use std::boxed::Box;
+use std::fs;
+
+use embedded_svc::http::server::{self, Connection, Middleware, Request};
+use embedded_svc::http::Method;
+
+use edge_frame_assets::prepare;
+
+use super::mock::MockConnection;
+use super::serve::{
+    self as assets, Asset, AssetMetadata, AssetPack, AssetPackHandler, AssetsHandler, Configuration,
+};
+
+const fn asset(
+    uri: &'static str,
+    content_encoding: Option<&'static str>,
+    content_type: Option<&'static str>,
+    data: &'static [u8],
+) -> Asset {
+    (
+        AssetMetadata {
+            uri,
+            cache_control: if uri.len() == 1 {
+                Some("no-cache")
+            } else {
+                Some("public, max-age=31536000")
+            },
+            content_encoding,
+            content_type,
+            etag: None,
+            headers: &[],
+        },
+        data,
+    )
+}
+
+const INDEX: &[u8] = b"<!DOCTYPE html><html></html>";
+const INDEX_GZ: &[u8] = b"gzipped index";
+const APP: &[u8] = b"console.log('a fairly long script, longer than its compressed variant');";
+const APP_GZ: &[u8] = b"gzipped app";
+const DATA: &[u8] = b"\x00\x01\x02\x03";
+const NOT_FOUND: &[u8] = b"<h1>Not found</h1>";
+const ERROR: &[u8] = b"<h1>Something went wrong</h1>";
+
+static ASSETS: &[Asset] = &[
+    asset("/", None, Some("text/html"), INDEX),
+    asset("/", Some("gzip"), Some("text/html"), INDEX_GZ),
+    asset("/app.js", None, Some("application/javascript"), APP),
+    asset(
+        "/app.js",
+        Some("gzip"),
+        Some("application/javascript"),
+        APP_GZ,
+    ),
+    asset("/data.xyz", None, None, DATA),
+    asset("/404.html", None, Some("text/html"), NOT_FOUND),
+    asset("/500.html", None, Some("text/html"), ERROR),
+];
+
+fn handle(connection: MockConnection) -> MockConnection {
+    handle_with(&AssetsHandler::new(ASSETS), connection)
+}
+
+fn handle_with(handler: &AssetsHandler, mut connection: MockConnection) -> MockConnection {
+    server::Handler::handle(handler, &mut connection).unwrap();
+
+    connection
+}
+
+fn handle_async(mut connection: MockConnection) -> MockConnection {
+    embassy_futures::block_on(server::asynch::Handler::handle(
+        &AssetsHandler::new(ASSETS),
+        &mut connection,
+    ))
+    .unwrap();
+
+    connection
+}
+
+#[test]
+fn gzip() {
+    for connection in [
+        handle(MockConnection::get("/app.js").with_header("Accept-Encoding", "gzip, br")),
+        handle_async(MockConnection::get("/app.js").with_header("Accept-Encoding", "gzip")),
+    ] {
+        let response = connection.response();
+
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, APP_GZ);
+        assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+        assert_eq!(
+            response.header("Content-Type"),
+            Some("application/javascript")
+        );
+        assert_eq!(response.header("Content-Length"), Some("11"));
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+        assert_eq!(
+            response.header("Cache-Control"),
+            Some("public, max-age=31536000")
+        );
+    }
+}
+
+#[test]
+fn gzip_not_accepted() {
+    for connection in [
+        handle(MockConnection::get("/app.js")),
+        handle_async(MockConnection::get("/app.js").with_header("Accept-Encoding", "gzip;q=0")),
+    ] {
+        let response = connection.response();
+
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, APP);
+        assert_eq!(response.header("Content-Encoding"), None);
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+    }
+}
+
+#[test]
+fn index() {
+    for uri in ["/", "/index.html", "/?query", "/settings/wifi"] {
+        for connection in [
+            handle(MockConnection::get(uri)),
+            handle_async(MockConnection::get(uri)),
+        ] {
+            let response = connection.response();
+
+            assert_eq!(response.status, 200, "{}", uri);
+            assert_eq!(response.body, INDEX, "{}", uri);
+            assert_eq!(response.header("Content-Type"), Some("text/html"));
+            assert_eq!(response.header("Cache-Control"), Some("no-cache"));
+        }
+    }
+
+    let connection = handle(MockConnection::get("/").with_header("Accept-Encoding", "gzip"));
+
+    assert_eq!(connection.response().body, INDEX_GZ);
+    assert_eq!(
+        connection.response().header("Content-Encoding"),
+        Some("gzip")
+    );
+}
+
+#[test]
+fn index_without_fallback() {
+    let handler = AssetsHandler::new(ASSETS).with_fallback(None);
+
+    let connection = handle_with(&handler, MockConnection::get("/settings/wifi"));
+
+    assert_eq!(connection.response().status, 404);
+
+    let connection = handle_with(&handler, MockConnection::get("/index.html"));
+
+    assert_eq!(connection.response().status, 200);
+}
+
+#[test]
+fn unknown_suffix() {
+    for connection in [
+        handle(MockConnection::get("/data.xyz")),
+        handle_async(MockConnection::get("/data.xyz")),
+    ] {
+        let response = connection.response();
+
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, DATA);
+        assert_eq!(response.header("Content-Type"), None);
+        assert_eq!(response.header("Vary"), None);
+    }
+
+    // Missing assets are not answered with the index page
+    for connection in [
+        handle(MockConnection::get("/missing.xyz")),
+        handle_async(MockConnection::get("/missing.xyz")),
+    ] {
+        let response = connection.response();
+
+        assert_eq!(response.status, 404);
+        assert!(response.body.is_empty());
+    }
+}
+
+fn pages() -> AssetsHandler {
+    AssetsHandler::new(ASSETS)
+        .with_not_found(Some("/404.html"))
+        .with_error(Some("/500.html"))
+}
+
+#[test]
+fn not_found_page() {
+    for uri in ["/missing.xyz", "/404.html?from=nowhere"] {
+        let connection = handle_with(&pages(), MockConnection::get(uri));
+        let response = connection.response();
+
+        let status = if uri.starts_with("/404.html") {
+            200
+        } else {
+            404
+        };
+
+        assert_eq!(response.status, status, "{}", uri);
+        assert_eq!(response.body, NOT_FOUND, "{}", uri);
+        assert_eq!(response.header("Content-Type"), Some("text/html"));
+    }
+
+    let connection = handle_with(&pages(), MockConnection::get("/missing.xyz"));
+    let response = connection.response();
+
+    // Neither cached as the missing asset nor subject to conditional requests
+    assert_eq!(response.header("Cache-Control"), Some("no-store"));
+    assert_eq!(response.header("Accept-Ranges"), None);
+
+    // The index fallback still takes precedence for app routes
+    let connection = handle_with(&pages(), MockConnection::get("/settings/wifi"));
+
+    assert_eq!(connection.response().status, 200);
+    assert_eq!(connection.response().body, INDEX);
+
+    let mut connection = MockConnection::new(Method::Head, "/missing.xyz");
+    embassy_futures::block_on(server::asynch::Handler::handle(&pages(), &mut connection)).unwrap();
+
+    assert_eq!(connection.response().status, 404);
+    assert!(connection.response().body.is_empty());
+    assert_eq!(connection.response().header("Content-Length"), Some("18"));
+}
+
+struct Failing(bool);
+
+impl<C: Connection> server::Handler<C> for Failing {
+    type Error = &'static str;
+
+    fn handle(&self, connection: &mut C) -> Result<(), Self::Error> {
+        if self.0 {
+            Request::wrap(connection)
+                .into_status_response(503)
+                .map_err(|_| "write failed")?;
+        }
+
+        Err("failed")
+    }
+}
+
+impl<C: server::asynch::Connection> server::asynch::Handler<C> for Failing {
+    type Error = &'static str;
+
+    async fn handle(&self, _connection: &mut C) -> Result<(), Self::Error> {
+        Err("failed")
+    }
+}
+
+#[test]
+fn error_page() {
+    let mut connection = MockConnection::new(Method::Post, "/api/scan");
+    let result = Middleware::handle(&pages(), &mut connection, &Failing(false));
+
+    assert_eq!(result, Err("failed"));
+
+    let response = connection.response();
+
+    assert_eq!(response.status, 500);
+    assert_eq!(response.body, ERROR);
+    assert_eq!(response.header("Content-Type"), Some("text/html"));
+
+    let mut connection = MockConnection::get("/api/scan");
+    let result = embassy_futures::block_on(server::asynch::Middleware::handle(
+        &pages(),
+        &mut connection,
+        &Failing(false),
+    ));
+
+    assert_eq!(result, Err("failed"));
+    assert_eq!(connection.response().status, 500);
+    assert_eq!(connection.response().body, ERROR);
+
+    // Responses already initiated by the failing handler are left alone
+    let mut connection = MockConnection::get("/api/scan");
+    let _ = Middleware::handle(&pages(), &mut connection, &Failing(true));
+
+    assert_eq!(connection.response().status, 503);
+    assert!(connection.response().body.is_empty());
+
+    // Without an error page, only the status is sent
+    let mut connection = MockConnection::get("/api/scan");
+    let _ = Middleware::handle(
+        &AssetsHandler::new(ASSETS),
+        &mut connection,
+        &Failing(false),
+    );
+
+    assert_eq!(connection.response().status, 500);
+    assert!(connection.response().body.is_empty());
+}
+
+#[test]
+fn head() {
+    let connection = handle(MockConnection::new(Method::Head, "/app.js"));
+    let response = connection.response();
+
+    assert_eq!(response.status, 200);
+    assert!(response.body.is_empty());
+    assert_eq!(response.header("Content-Length"), Some("72"));
+}
+
+#[test]
+fn options_and_unsupported_methods() {
+    let connection = handle(MockConnection::new(Method::Options, "/app.js"));
+
+    assert_eq!(connection.response().status, 204);
+    assert_eq!(
+        connection.response().header("Allow"),
+        Some("GET, HEAD, OPTIONS")
+    );
+
+    let connection = handle(MockConnection::new(Method::Post, "/app.js"));
+
+    assert_eq!(connection.response().status, 405);
+    assert_eq!(connection.response().header("Content-Length"), Some("0"));
+}
+
+#[test]
+fn range() {
+    let connection = handle(MockConnection::get("/app.js").with_header("Range", "bytes=0-6"));
+    let response = connection.response();
+
+    assert_eq!(response.status, 206);
+    assert_eq!(response.body, b"console");
+    assert_eq!(response.header("Content-Range"), Some("bytes 0-6/72"));
+
+    let connection = handle(MockConnection::get("/app.js").with_header("Range", "bytes=100-"));
+
+    assert_eq!(connection.response().status, 416);
+    assert_eq!(
+        connection.response().header("Content-Range"),
+        Some("bytes */72")
+    );
+}
+
+#[test]
+fn serve_asset_data() {
+    let metadata = AssetMetadata {
+        etag: Some("\"abc\""),
+        headers: &[("X-Content-Type-Options", "nosniff")],
+        ..ASSETS[2].0.clone()
+    };
+
+    let mut connection = MockConnection::get("/app.js");
+    assets::serve_asset_data(Request::wrap(&mut connection), metadata.clone(), APP).unwrap();
+
+    let response = connection.response();
+
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, APP);
+    assert_eq!(response.header("ETag"), Some("\"abc\""));
+    assert_eq!(response.header("X-Content-Type-Options"), Some("nosniff"));
+
+    let mut connection = MockConnection::get("/app.js").with_header("If-None-Match", "\"abc\"");
+    assets::serve_asset_data(Request::wrap(&mut connection), metadata, APP).unwrap();
+
+    assert_eq!(connection.response().status, 304);
+    assert!(connection.response().body.is_empty());
+}
+
+#[test]
+fn serve_in_chunks() {
+    let mut conf = Configuration::new();
+    conf.chunk_size = 5;
+    conf.headers = &[("Content-Security-Policy", "default-src 'self'")];
+
+    let mut connection = MockConnection::get("/app.js");
+    assets::serve_asset_data_with_conf(
+        Request::wrap(&mut connection),
+        ASSETS[2].0.clone(),
+        APP,
+        &conf,
+    )
+    .unwrap();
+
+    assert_eq!(connection.response().body, APP);
+    assert!(connection.response().writes.len() > 1);
+    assert!(connection
+        .response()
+        .writes
+        .iter()
+        .all(|len| *len <= conf.chunk_size));
+    assert_eq!(
+        connection.response().header("Content-Security-Policy"),
+        Some("default-src 'self'")
+    );
+}
+
+#[test]
+fn async_asset_handler() {
+    let handler =
+        assets::asynch::AssetHandler::from_asset(ASSETS[4].clone()).with_conf(Configuration {
+            chunk_size: 1,
+            ..Configuration::new()
+        });
+
+    let mut connection = MockConnection::get("/whatever");
+    embassy_futures::block_on(server::asynch::Handler::handle(&handler, &mut connection)).unwrap();
+
+    let response = connection.response();
+
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, DATA);
+    assert_eq!(response.header("Content-Length"), Some("4"));
+    assert_eq!(response.header("Content-Type"), None);
+}
+
+/// Writes an asset pack of `files` with `prepare::run_pack`
+fn asset_pack(files: &[(&str, &[u8])]) -> &'static [u8] {
+    let conf = prepare::Configuration {
+        identity: true,
+        ..Default::default()
+    };
+
+    let pack = prepare::with_temp_dir("edge-frame-test", |dir| {
+        for (uri, data) in files {
+            let path = dir.join("assets").join(&uri[1..]);
+
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(path, data)?;
+        }
+
+        let pack_file = dir.join("assets.pack");
+
+        prepare::run_pack(dir.join("assets"), &pack_file, 1, &conf)?;
+
+        Ok(fs::read(pack_file)?)
+    })
+    .unwrap();
+
+    Box::leak(pack.into_boxed_slice())
+}
+
+/// A script long and repetitive enough for its gzipped variant to be embedded
+const PACK_APP: &[u8] =
+    b"console.log('app'); console.log('app'); console.log('app'); console.log('app');";
+
+#[test]
+fn asset_pack_handler() {
+    let handler = AssetPackHandler::new(
+        AssetPack::new(asset_pack(&[
+            ("/index.html", INDEX),
+            ("/app.js", PACK_APP),
+            ("/404.html", NOT_FOUND),
+        ]))
+        .unwrap(),
+    )
+    .with_not_found(Some("/404.html"));
+
+    let handle = |connection| {
+        let mut connection = connection;
+        server::Handler::handle(&handler, &mut connection).unwrap();
+
+        connection
+    };
+
+    let handle_async = |connection| {
+        let mut connection = connection;
+        embassy_futures::block_on(server::asynch::Handler::handle(&handler, &mut connection))
+            .unwrap();
+
+        connection
+    };
+
+    for connection in [
+        handle(MockConnection::get("/app.js").with_header("Accept-Encoding", "gzip")),
+        handle_async(MockConnection::get("/app.js").with_header("Accept-Encoding", "gzip")),
+    ] {
+        let response = connection.response();
+
+        assert_eq!(response.status, 200);
+        assert!(response.body.starts_with(b"\x1f\x8b"));
+        assert!(response.body.len() < PACK_APP.len());
+        assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.header("Content-Type"), Some("text/javascript"));
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+    }
+
+    for connection in [
+        handle(MockConnection::get("/app.js")),
+        handle_async(MockConnection::get("/app.js")),
+    ] {
+        let response = connection.response();
+
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, PACK_APP);
+        assert_eq!(response.header("Content-Encoding"), None);
+    }
+
+    for connection in [
+        handle(MockConnection::get("/settings/wifi")),
+        handle_async(MockConnection::get("/settings/wifi")),
+    ] {
+        assert_eq!(connection.response().status, 200);
+        assert_eq!(connection.response().body, INDEX);
+        assert_eq!(
+            connection.response().header("Cache-Control"),
+            Some("no-cache")
+        );
+    }
+
+    let connection = handle(MockConnection::get("/missing.xyz"));
+
+    assert_eq!(connection.response().status, 404);
+    assert_eq!(connection.response().body, NOT_FOUND);
+}
+
+#[cfg(feature = "assets-serve-timeout")]
+#[test]
+fn write_timeout() {
+    use core::time::Duration;
+
+    let mut conf = Configuration::new();
+    conf.chunk_size = 5;
+    conf.write_timeout = Some(embassy_time::Duration::from_millis(30));
+
+    let slow = || MockConnection::get("/app.js").with_write_delay(Duration::from_millis(20));
+
+    let mut connection = slow();
+    let result = assets::serve_asset_data_with_conf(
+        Request::wrap(&mut connection),
+        ASSETS[2].0.clone(),
+        APP,
+        &conf,
+    );
+
+    assert_eq!(result, Err(assets::ServeError::WriteTimeout));
+    assert!(connection.response().body.len() < APP.len());
+
+    let mut connection = slow();
+    let result = embassy_futures::block_on(assets::asynch::serve_asset_data_with_conf(
+        server::asynch::Request::wrap(&mut connection),
+        ASSETS[2].0.clone(),
+        APP,
+        &conf,
+    ));
+
+    assert_eq!(result, Err(assets::ServeError::WriteTimeout));
+    assert!(connection.response().body.len() < APP.len());
+}
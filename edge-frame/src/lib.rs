@@ -40,5 +40,8 @@ mod web {
 #[cfg(any(feature = "assets-serve", feature = "assets-prepare"))]
 pub mod assets;
 
+#[cfg(feature = "assets-macro")]
+pub use edge_frame_macros::assets;
+
 #[cfg(feature = "dto")]
 pub mod dto;
@@ -1,18 +1,21 @@
-use core::cell::RefCell;
+use core::cell::{Cell, RefCell};
 use core::fmt::Debug;
+use core::time::Duration;
 
 extern crate alloc;
 use alloc::rc::Rc;
 
-use log::{log, trace, Level};
+use log::{info, log, trace, warn, Level};
 
 use embassy_sync::channel;
 use serde::{de::DeserializeOwned, Serialize};
 
+use futures::channel::mpsc;
+use futures::future::{self, Either};
 use futures::stream::{SplitSink, SplitStream};
 use futures::{SinkExt, StreamExt};
 
-use gloo_net::websocket::{futures::WebSocket, Message};
+use gloo_net::websocket::{futures::WebSocket, Message, State};
 
 use postcard::to_allocvec;
 
@@ -92,11 +95,7 @@ pub fn receive_local<M>(
 pub fn open(
     ws_endpoint: &str,
 ) -> Result<(SplitSink<WebSocket, Message>, SplitStream<WebSocket>), JsError> {
-    open_url(&format!(
-        "ws://{}{}",
-        web_sys::window().unwrap().location().host().unwrap(),
-        ws_endpoint,
-    ))
+    open_url(&url(ws_endpoint)?)
 }
 
 fn open_url(url: &str) -> Result<(SplitSink<WebSocket, Message>, SplitStream<WebSocket>), JsError> {
@@ -105,6 +104,16 @@ fn open_url(url: &str) -> Result<(SplitSink<WebSocket, Message>, SplitStream<Web
     Ok(ws.split())
 }
 
+fn url(ws_endpoint: &str) -> Result<String, JsError> {
+    let host = web_sys::window()
+        .ok_or_else(|| JsError::new("No window"))?
+        .location()
+        .host()
+        .map_err(|_| JsError::new("Cannot get the host of the page"))?;
+
+    Ok(format!("ws://{}{}", host, ws_endpoint))
+}
+
 #[allow(clippy::await_holding_refcell_ref)]
 pub fn send<M>(sender: SplitSink<WebSocket, Message>) -> impl Fn(&MiddlewareContext, M)
 where
@@ -118,12 +127,15 @@ where
         spawn_local(async move {
             trace!("Sending request: {:?}", msg);
 
+            let Some(message) = serialize(&msg) else {
+                return;
+            };
+
             let mut guard = sender.borrow_mut();
 
-            guard
-                .send(Message::Bytes(to_allocvec(&msg).unwrap()))
-                .await
-                .unwrap();
+            if let Err(err) = guard.send(message).await {
+                warn!("Sending request {:?} failed: {}", msg, err);
+            }
         });
     }
 }
@@ -136,16 +148,259 @@ where
 
     spawn_local(async move {
         loop {
-            let event = receiver.next().await.unwrap().unwrap();
-            trace!("Received event: {:?}", event);
+            let event = match receiver.next().await {
+                Some(Ok(event)) => event,
+                Some(Err(err)) => {
+                    warn!("Receiving events failed: {}", err);
+                    break;
+                }
+                None => {
+                    info!("WebSocket closed");
+                    break;
+                }
+            };
 
-            if let Message::Bytes(bytes) = event {
-                let event = postcard::from_bytes(&bytes).unwrap();
+            trace!("Received event: {:?}", event);
 
+            if let Some(event) = deserialize::<M>(event) {
                 mcx.invoke::<M>(event);
-            } else {
-                panic!("Unexpected message type: Text");
             }
         }
     });
 }
+
+/// The store with the state of the connection managed by `connect`.
+///
+/// `connect` registers a dispatch of `ConnectionState` updating this store, and publishes
+/// each change of the state to the middleware context.
+#[derive(Default, Clone, Debug, PartialEq, Eq, Store)]
+pub struct ConnectionStore(pub Option<ConnectionState>);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// A connection is being opened
+    Connecting,
+    /// The connection is open
+    Connected,
+    /// The connection failed or was lost; it is re-opened after the `Backoff` delay
+    Disconnected,
+}
+
+impl Reducer<ConnectionStore> for ConnectionState {
+    fn apply(self, mut store: Rc<ConnectionStore>) -> Rc<ConnectionStore> {
+        let state = Rc::make_mut(&mut store);
+
+        state.0 = Some(self);
+
+        store
+    }
+}
+
+/// The delays between the attempts of `connect` to re-open the connection: the first attempt
+/// is made after `initial`, and each failed attempt doubles the delay up to `max`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(500),
+            max: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Opens a WebSocket connection to `ws_endpoint` on the host of the page, which is re-opened
+/// with `backoff` whenever it fails (e.g. when the device reboots), and returns the middleware
+/// sending the `S` requests over it.
+///
+/// The `R` events received over the connection are dispatched to `mcx`, the same way `receive`
+/// does it, and so are the changes of its `ConnectionState`.
+///
+/// The requests are not queued for later: the ones sent while the connection is not open,
+/// and the ones whose sending fails, are dropped with a warning. Apps needing a request to
+/// reach the device can resend it on `ConnectionState::Connected`.
+///
+/// `connect` registers a dispatch of `ConnectionState` updating `ConnectionStore`.
+/// To also run other middleware on the state changes, register a dispatch of `ConnectionState`
+/// after calling `connect`.
+pub fn connect<S, R>(
+    mcx: &MiddlewareContext,
+    ws_endpoint: &str,
+    backoff: Backoff,
+) -> Result<impl Fn(&MiddlewareContext, S), JsError>
+where
+    S: Serialize + Debug + 'static,
+    R: DeserializeOwned + Debug + 'static,
+{
+    Ok(connect_url::<S, R>(mcx, url(ws_endpoint)?, backoff))
+}
+
+/// Same as `connect`, with the URL of the WebSocket endpoint
+pub fn connect_url<S, R>(
+    mcx: &MiddlewareContext,
+    url: String,
+    backoff: Backoff,
+) -> impl Fn(&MiddlewareContext, S)
+where
+    S: Serialize + Debug + 'static,
+    R: DeserializeOwned + Debug + 'static,
+{
+    mcx.register(MiddlewareContext::store::<ConnectionState, ConnectionStore>);
+
+    let (sender, mut receiver) = mpsc::unbounded::<Message>();
+
+    let connected = Rc::new(Cell::new(false));
+
+    let mcx = mcx.clone();
+
+    let task_connected = connected.clone();
+
+    spawn_local(async move {
+        let mut delay = backoff.initial;
+
+        loop {
+            mcx.invoke(ConnectionState::Connecting);
+
+            match open_connected(&url).await {
+                Ok(ws) => {
+                    info!("Connected to {}", url);
+
+                    task_connected.set(true);
+
+                    mcx.invoke(ConnectionState::Connected);
+
+                    delay = backoff.initial;
+
+                    let requests = run_connected::<R>(&mcx, ws, &mut receiver).await;
+
+                    task_connected.set(false);
+
+                    if !requests {
+                        // The middleware sending the requests is gone, so is the need for a connection
+                        return;
+                    }
+
+                    // Drop the requests which were sent before the connection was lost
+                    while receiver.try_recv().is_ok() {
+                        warn!("Connection lost, dropping request");
+                    }
+                }
+                Err(err) => warn!("Connecting to {} failed: {:?}", url, err),
+            }
+
+            mcx.invoke(ConnectionState::Disconnected);
+
+            info!("Reconnecting to {} in {}ms", url, delay.as_millis());
+
+            yew::platform::time::sleep(delay).await;
+
+            delay = delay.saturating_mul(2).min(backoff.max);
+        }
+    });
+
+    move |_ctx, msg| {
+        if !connected.get() {
+            warn!("Not connected, dropping request {:?}", msg);
+            return;
+        }
+
+        trace!("Sending request: {:?}", msg);
+
+        if let Some(message) = serialize(&msg) {
+            // The connection task only ends once this middleware is dropped
+            let _ = sender.unbounded_send(message);
+        }
+    }
+}
+
+/// Opens a WebSocket connection and waits until it is open
+async fn open_connected(url: &str) -> Result<WebSocket, JsError> {
+    let mut ws = WebSocket::open(url)?;
+
+    // The socket becomes ready once it is either open or failed
+    future::poll_fn(|cx| ws.poll_ready_unpin(cx))
+        .await
+        .map_err(|err| JsError::new(&err.to_string()))?;
+
+    if matches!(ws.state(), State::Open) {
+        Ok(ws)
+    } else {
+        Err(JsError::new("Connection failed"))
+    }
+}
+
+/// Exchanges the requests and the events over `ws` until the connection is lost, and returns
+/// `false` if the requests ended instead
+async fn run_connected<R>(
+    mcx: &MiddlewareContext,
+    ws: WebSocket,
+    requests: &mut mpsc::UnboundedReceiver<Message>,
+) -> bool
+where
+    R: DeserializeOwned + Debug + 'static,
+{
+    let (mut sink, mut stream) = ws.split();
+
+    loop {
+        match future::select(requests.next(), stream.next()).await {
+            Either::Left((Some(request), _)) => {
+                if let Err(err) = sink.send(request).await {
+                    warn!("Sending a request failed, dropping it: {}", err);
+                    return true;
+                }
+            }
+            Either::Left((None, _)) => return false,
+            Either::Right((Some(Ok(event)), _)) => {
+                trace!("Received event: {:?}", event);
+
+                if let Some(event) = deserialize::<R>(event) {
+                    mcx.invoke::<R>(event);
+                }
+            }
+            Either::Right((Some(Err(err)), _)) => {
+                warn!("Connection lost: {}", err);
+                return true;
+            }
+            Either::Right((None, _)) => {
+                warn!("Connection closed");
+                return true;
+            }
+        }
+    }
+}
+
+fn serialize<M>(msg: &M) -> Option<Message>
+where
+    M: Serialize + Debug,
+{
+    match to_allocvec(msg) {
+        Ok(bytes) => Some(Message::Bytes(bytes)),
+        Err(err) => {
+            warn!("Serializing request {:?} failed: {}", msg, err);
+            None
+        }
+    }
+}
+
+fn deserialize<M>(event: Message) -> Option<M>
+where
+    M: DeserializeOwned,
+{
+    match event {
+        Message::Bytes(bytes) => match postcard::from_bytes(&bytes) {
+            Ok(event) => Some(event),
+            Err(err) => {
+                warn!("Deserializing event failed: {}", err);
+                None
+            }
+        },
+        Message::Text(_) => {
+            warn!("Unexpected message type: Text");
+            None
+        }
+    }
+}